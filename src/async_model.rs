@@ -0,0 +1,93 @@
+use std::path::PathBuf;
+use std::result::Result;
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
+use tokio::task;
+
+use super::model::{InMemoryModel, Model, Ranking};
+
+/// Handle to an [`InMemoryModel`] shared between tasks of a tokio runtime.
+/// Tokenization and scoring run on the blocking thread pool so they never
+/// stall the runtime, and the index itself is guarded by an async lock.
+/// Clones refer to the same index.
+#[derive(Clone, Default)]
+pub struct AsyncModel {
+    model: Arc<RwLock<InMemoryModel>>,
+}
+
+impl AsyncModel {
+    pub fn new(model: InMemoryModel) -> Self {
+        Self { model: Arc::new(RwLock::new(model)) }
+    }
+
+    /// Tokenizes `content` on the blocking pool and then adds it to the index.
+    /// The write lock is only held for the final insertion.
+    pub async fn add_document_async(&self, file_path: PathBuf, content: Vec<char>) -> Result<(), ()> {
+        let lexer = self.model.read().await.config().lexer();
+        let document = task::spawn_blocking(move || InMemoryModel::analyze(lexer, &content)).await.map_err(|err| {
+            eprintln!("ERROR: could not analyze document: {err}");
+        })?;
+        self.model.write().await.add_analyzed(file_path, document)
+    }
+
+    pub async fn search_query_async(&self, query: Vec<char>) -> Result<Ranking, ()> {
+        let model = self.model.clone().read_owned().await;
+        task::spawn_blocking(move || model.search_query(&query)).await.map_err(|err| {
+            eprintln!("ERROR: could not search: {err}");
+        })?
+    }
+
+    pub async fn document_count_async(&self) -> Result<usize, ()> {
+        self.model.read().await.document_count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    fn content(i: usize) -> Vec<char> {
+        chars(&format!("shared doc{i} {parity}", parity = if i.is_multiple_of(2) { "even" } else { "odd" }))
+    }
+
+    #[test]
+    fn concurrent_adds_and_searches() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let model = AsyncModel::default();
+        runtime.block_on(async {
+            let mut tasks = Vec::new();
+            for i in 0..20 {
+                let model = model.clone();
+                tasks.push(tokio::spawn(async move {
+                    model.add_document_async(PathBuf::from(format!("{i}.txt")), content(i)).await.unwrap();
+                    // Whatever was added so far, the search sees whole documents.
+                    let ranking = model.search_query_async(chars("shared")).await.unwrap();
+                    assert!(!ranking.is_empty());
+                }));
+            }
+            for task in tasks {
+                task.await.unwrap();
+            }
+
+            assert_eq!(model.document_count_async().await.unwrap(), 20);
+            let even = model.search_query_async(chars("even")).await.unwrap();
+            assert_eq!(even.len(), 10);
+            assert!(even.iter().all(|(path, _)| path.to_str().unwrap().trim_end_matches(".txt").parse::<usize>().unwrap().is_multiple_of(2)));
+            let doc7 = model.search_query_async(chars("doc7")).await.unwrap();
+            assert_eq!(doc7[0].0, PathBuf::from("7.txt"));
+        });
+        let expected = {
+            let mut model = InMemoryModel::default();
+            for i in 0..20 {
+                model.add_document(PathBuf::from(format!("{i}.txt")), &content(i)).unwrap();
+            }
+            model.search_query(&chars("odd doc3")).unwrap()
+        };
+        assert_eq!(runtime.block_on(model.search_query_async(chars("odd doc3"))).unwrap(), expected);
+    }
+}
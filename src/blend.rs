@@ -0,0 +1,116 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use super::model::{DocFreq, TermFreq};
+
+/// What a [`ScoreComponent`] gets to see of a document being scored and of
+/// the corpus it is in.
+pub struct DocumentStats<'a> {
+    pub path: &'a Path,
+    pub tf: &'a TermFreq,
+    /// Length of the document in terms.
+    pub term_count: usize,
+    pub df: &'a DocFreq,
+    pub document_count: usize,
+    /// Normalized query terms with their weights.
+    pub query_terms: &'a [(String, f32)],
+}
+
+/// One signal of a [`BlendedScorer`].
+pub trait ScoreComponent {
+    fn score(&self, stats: &DocumentStats) -> f32;
+}
+
+/// Plain TF-IDF relevance, as scored by [`crate::model::Scorer::TfIdf`]
+/// without any of the model's boosts.
+pub struct TfIdf;
+
+impl ScoreComponent for TfIdf {
+    fn score(&self, stats: &DocumentStats) -> f32 {
+        if stats.term_count == 0 || stats.document_count == 0 {
+            return 0.0
+        }
+        stats.query_terms.iter().map(|(term, weight)| {
+            let tf = stats.tf.get(term).cloned().unwrap_or(0) as f32 / stats.term_count as f32;
+            let df = stats.df.get(term).cloned().unwrap_or(1) as f32;
+            weight * tf * (stats.document_count as f32 / df).log10()
+        }).sum()
+    }
+}
+
+/// A fixed score per document, `default` for the ones not in `boosts`, e.g.
+/// to favour authoritative documents whatever the query.
+pub struct DocumentBoost {
+    pub boosts: HashMap<PathBuf, f32>,
+    pub default: f32,
+}
+
+impl ScoreComponent for DocumentBoost {
+    fn score(&self, stats: &DocumentStats) -> f32 {
+        self.boosts.get(stats.path).cloned().unwrap_or(self.default)
+    }
+}
+
+/// Fraction of the distinct query terms that occur in the document's path,
+/// ignoring case.
+pub struct PathMatch;
+
+impl ScoreComponent for PathMatch {
+    fn score(&self, stats: &DocumentStats) -> f32 {
+        let terms = stats.query_terms.iter().map(|(term, _)| term.to_uppercase()).collect::<HashSet<_>>();
+        if terms.is_empty() {
+            return 0.0
+        }
+        let path = stats.path.to_string_lossy().to_uppercase();
+        terms.iter().filter(|term| path.contains(term.as_str())).count() as f32 / terms.len() as f32
+    }
+}
+
+/// Weighted sum of several [`ScoreComponent`]s, for experimenting with
+/// combinations of ranking signals, see [`crate::model::InMemoryModel::search_blended`].
+#[derive(Default)]
+pub struct BlendedScorer {
+    components: Vec<(Box<dyn ScoreComponent>, f32)>,
+}
+
+impl BlendedScorer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<C>(mut self, component: C, weight: f32) -> Self where C: ScoreComponent + 'static {
+        self.components.push((Box::new(component), weight));
+        self
+    }
+
+    pub fn score(&self, stats: &DocumentStats) -> f32 {
+        self.components.iter().map(|(component, weight)| weight * component.score(stats)).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{InMemoryModel, Model};
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn blended_score_is_the_weighted_sum_of_the_components() {
+        let mut model = InMemoryModel::default();
+        for (path, content) in [("a.txt", "rust search engine rust"), ("b.txt", "rust"), ("c.txt", "python")] {
+            model.add_document(PathBuf::from(path), &chars(content)).unwrap();
+        }
+        let boost = DocumentBoost { boosts: HashMap::from([(PathBuf::from("b.txt"), 3.0)]), default: 1.0 };
+        let scorer = BlendedScorer::new().with(TfIdf, 2.0).with(boost, 0.5);
+        let ranking = model.search_blended(&chars("rust engine"), &scorer).unwrap();
+
+        let tfidf = |path: &str| model.explain(&chars("rust engine"), Path::new(path)).unwrap().score;
+        let score = |path: &str| ranking.iter().find(|(other, _)| other == Path::new(path)).unwrap().1;
+        assert!((score("a.txt") - (2.0 * tfidf("a.txt") + 0.5 * 1.0)).abs() < 1e-6);
+        assert!((score("b.txt") - (2.0 * tfidf("b.txt") + 0.5 * 3.0)).abs() < 1e-6);
+        assert_eq!(ranking.len(), 2);
+    }
+}
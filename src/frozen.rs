@@ -0,0 +1,465 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::result::Result;
+use std::str;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use memmap2::Mmap;
+
+use super::lexer::{Lexer, LexerConfig};
+use super::model::{sort_ranking, Ranking};
+
+// File layout, every integer a little endian u64 and every offset relative to
+// the start of the file:
+//
+//   header    MAGIC, VERSION, document count, term count, config offset,
+//             config length, document table offset, term table offset
+//   config    the LexerConfig as JSON
+//   documents (path offset, path length, term count) per document
+//   terms     (term offset, term length, postings offset, postings length)
+//             per term, sorted by term
+//   postings  (document index, term frequency) per document containing a term
+//   strings   paths and terms as UTF-8
+const MAGIC: &[u8; 8] = b"SEARUSTF";
+const VERSION: u64 = 1;
+const HEADER_LEN: usize = 8 * 8;
+const DOC_ENTRY_LEN: usize = 3 * 8;
+const TERM_ENTRY_LEN: usize = 4 * 8;
+const POSTING_LEN: usize = 2 * 8;
+
+/// Read-only snapshot of an index made with [`crate::model::InMemoryModel::freeze`],
+/// with a sorted term table and compact postings instead of hash maps.
+pub struct FrozenModel {
+    lexer: LexerConfig,
+    docs: Vec<(PathBuf, usize)>,
+    terms: Vec<(String, Vec<(usize, usize)>)>,
+}
+
+/// The shape shared by [`FrozenModel`] and [`MappedModel`], so both search
+/// exactly the same way.
+trait FrozenIndex {
+    fn lexer(&self) -> LexerConfig;
+    fn document_count(&self) -> usize;
+    /// Path and term count of the document at `index`.
+    fn document(&self, index: usize) -> (&Path, usize);
+    /// `(document index, term frequency)` of every document containing `term`.
+    fn postings(&self, term: &str) -> impl ExactSizeIterator<Item = (usize, usize)> + '_;
+}
+
+fn search<I: FrozenIndex>(index: &I, query: &[char]) -> Ranking {
+    let mut weights = HashMap::<String, f32>::new();
+    for term in Lexer::with_config(query, index.lexer()) {
+        *weights.entry(term).or_default() += 1.0;
+    }
+
+    let n = index.document_count() as f32;
+    let mut scores = HashMap::<usize, f32>::new();
+    for (term, weight) in &weights {
+        let postings = index.postings(term);
+        if postings.len() == 0 {
+            continue
+        }
+        let idf = (n / postings.len() as f32).log10();
+        for (doc, freq) in postings {
+            let (_, count) = index.document(doc);
+            *scores.entry(doc).or_default() += weight * freq as f32 / count as f32 * idf;
+        }
+    }
+
+    let mut ranking = scores.into_iter()
+        .map(|(doc, score)| (index.document(doc).0.to_path_buf(), score))
+        .collect();
+    sort_ranking(&mut ranking);
+    ranking
+}
+
+impl FrozenIndex for FrozenModel {
+    fn lexer(&self) -> LexerConfig {
+        self.lexer
+    }
+
+    fn document_count(&self) -> usize {
+        self.docs.len()
+    }
+
+    fn document(&self, index: usize) -> (&Path, usize) {
+        let (path, count) = &self.docs[index];
+        (path, *count)
+    }
+
+    fn postings(&self, term: &str) -> impl ExactSizeIterator<Item = (usize, usize)> + '_ {
+        let postings = match self.terms.binary_search_by(|(other, _)| other.as_str().cmp(term)) {
+            Ok(i) => &self.terms[i].1[..],
+            Err(_) => &[],
+        };
+        postings.iter().copied()
+    }
+}
+
+impl FrozenModel {
+    /// `terms` must be sorted and refer to documents by their index in `docs`.
+    pub(crate) fn new(lexer: LexerConfig, docs: Vec<(PathBuf, usize)>, terms: Vec<(String, Vec<(usize, usize)>)>) -> Self {
+        Self { lexer, docs, terms }
+    }
+
+    /// Documents matching `query`, best first. Documents sharing none of its
+    /// terms are left out.
+    pub fn search_query(&self, query: &[char]) -> Ranking {
+        search(self, query)
+    }
+
+    pub fn document_count(&self) -> usize {
+        self.docs.len()
+    }
+
+    /// Saves the snapshot in a binary layout that [`FrozenModel::mmap`] can
+    /// search in place.
+    pub fn write_to(&self, path: &Path) -> Result<(), ()> {
+        let config = serde_json::to_vec(&self.lexer).map_err(|err| {
+            eprintln!("ERROR: could not serialize lexer configuration: {err}");
+        })?;
+
+        let config_offset = HEADER_LEN;
+        let docs_offset = config_offset + config.len();
+        let terms_offset = docs_offset + self.docs.len() * DOC_ENTRY_LEN;
+        let postings_offset = terms_offset + self.terms.len() * TERM_ENTRY_LEN;
+        let postings_count = self.terms.iter().map(|(_, postings)| postings.len()).sum::<usize>();
+        let strings_offset = postings_offset + postings_count * POSTING_LEN;
+
+        let mut tables = Vec::with_capacity(strings_offset);
+        let mut strings = Vec::new();
+        let push = |bytes: &mut Vec<u8>, value: usize| bytes.extend_from_slice(&(value as u64).to_le_bytes());
+
+        tables.extend_from_slice(MAGIC);
+        for value in [VERSION as usize, self.docs.len(), self.terms.len(), config_offset, config.len(), docs_offset, terms_offset] {
+            push(&mut tables, value);
+        }
+        tables.extend_from_slice(&config);
+
+        for (path, count) in &self.docs {
+            let path = path.to_str().ok_or_else(|| {
+                eprintln!("ERROR: could not store non UTF-8 path {path}", path = path.display());
+            })?;
+            push(&mut tables, strings_offset + strings.len());
+            push(&mut tables, path.len());
+            push(&mut tables, *count);
+            strings.extend_from_slice(path.as_bytes());
+        }
+
+        let mut postings_at = postings_offset;
+        for (term, postings) in &self.terms {
+            push(&mut tables, strings_offset + strings.len());
+            push(&mut tables, term.len());
+            push(&mut tables, postings_at);
+            push(&mut tables, postings.len());
+            strings.extend_from_slice(term.as_bytes());
+            postings_at += postings.len() * POSTING_LEN;
+        }
+
+        for (_, postings) in &self.terms {
+            for (doc, freq) in postings {
+                push(&mut tables, *doc);
+                push(&mut tables, *freq);
+            }
+        }
+
+        let log_err = |err| {
+            eprintln!("ERROR: could not write frozen index {path}: {err}", path = path.display());
+        };
+        let mut file = BufWriter::new(File::create(path).map_err(log_err)?);
+        file.write_all(&tables).map_err(log_err)?;
+        file.write_all(&strings).map_err(log_err)?;
+        file.flush().map_err(log_err)
+    }
+
+    /// Maps a file written by [`FrozenModel::write_to`] into memory to search it
+    /// without deserializing, so processes serving the same index share it
+    /// through the page cache. The tables are validated up front so a
+    /// truncated or foreign file is an error rather than a panic later.
+    pub fn mmap(path: &Path) -> Result<MappedModel, ()> {
+        let file = File::open(path).map_err(|err| {
+            eprintln!("ERROR: could not open frozen index {path}: {err}", path = path.display());
+        })?;
+        // SAFETY: the file is only read through the mapping; modifying it while
+        // mapped is not supported, like for any other index file.
+        let bytes = unsafe { Mmap::map(&file) }.map_err(|err| {
+            eprintln!("ERROR: could not map frozen index {path}: {err}", path = path.display());
+        })?;
+        MappedModel::new(bytes).map_err(|reason| {
+            eprintln!("ERROR: invalid frozen index {path}: {reason}", path = path.display());
+        })
+    }
+}
+
+/// A [`FrozenModel`] searched directly in the pages of a memory mapped file,
+/// see [`FrozenModel::mmap`].
+pub struct MappedModel {
+    bytes: Mmap,
+    lexer: LexerConfig,
+    doc_count: usize,
+    term_count: usize,
+    docs_offset: usize,
+    terms_offset: usize,
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> Option<usize> {
+    let value = bytes.get(at..at.checked_add(8)?)?;
+    usize::try_from(u64::from_le_bytes(value.try_into().ok()?)).ok()
+}
+
+fn read_str(bytes: &[u8], offset: usize, len: usize) -> Option<&str> {
+    str::from_utf8(bytes.get(offset..offset.checked_add(len)?)?).ok()
+}
+
+impl MappedModel {
+    fn new(bytes: Mmap) -> Result<Self, &'static str> {
+        if bytes.get(..MAGIC.len()) != Some(MAGIC) {
+            return Err("not a frozen index")
+        }
+        let header = |i: usize| read_u64(&bytes, MAGIC.len() + i * 8).ok_or("truncated header");
+        if header(0)? as u64 != VERSION {
+            return Err("unsupported version")
+        }
+        let (doc_count, term_count) = (header(1)?, header(2)?);
+        let (config_offset, config_len) = (header(3)?, header(4)?);
+        let (docs_offset, terms_offset) = (header(5)?, header(6)?);
+
+        let config = bytes.get(config_offset..config_offset.saturating_add(config_len)).ok_or("truncated configuration")?;
+        let lexer = serde_json::from_slice(config).map_err(|_| "invalid configuration")?;
+
+        let this = Self { bytes, lexer, doc_count, term_count, docs_offset, terms_offset };
+        for doc in 0..doc_count {
+            this.document_entry(doc).ok_or("invalid document table")?;
+        }
+        let mut previous: Option<&str> = None;
+        for term in 0..term_count {
+            let (term, postings_offset, postings_len) = this.term_entry(term).ok_or("invalid term table")?;
+            if previous.is_some_and(|previous| previous >= term) {
+                return Err("unsorted term table")
+            }
+            previous = Some(term);
+            for posting in 0..postings_len {
+                let doc = read_u64(&this.bytes, postings_offset + posting * POSTING_LEN).ok_or("truncated postings")?;
+                read_u64(&this.bytes, postings_offset + posting * POSTING_LEN + 8).ok_or("truncated postings")?;
+                if doc >= doc_count {
+                    return Err("posting of unknown document")
+                }
+            }
+        }
+        Ok(this)
+    }
+
+    fn document_entry(&self, index: usize) -> Option<(&str, usize)> {
+        let at = self.docs_offset.checked_add(index.checked_mul(DOC_ENTRY_LEN)?)?;
+        let path = read_str(&self.bytes, read_u64(&self.bytes, at)?, read_u64(&self.bytes, at + 8)?)?;
+        Some((path, read_u64(&self.bytes, at + 16)?))
+    }
+
+    fn term_entry(&self, index: usize) -> Option<(&str, usize, usize)> {
+        let at = self.terms_offset.checked_add(index.checked_mul(TERM_ENTRY_LEN)?)?;
+        let term = read_str(&self.bytes, read_u64(&self.bytes, at)?, read_u64(&self.bytes, at + 8)?)?;
+        Some((term, read_u64(&self.bytes, at + 16)?, read_u64(&self.bytes, at + 24)?))
+    }
+
+    pub fn search_query(&self, query: &[char]) -> Ranking {
+        search(self, query)
+    }
+
+    pub fn document_count(&self) -> usize {
+        self.doc_count
+    }
+}
+
+// The entries were all validated in MappedModel::new, hence the expects.
+impl FrozenIndex for MappedModel {
+    fn lexer(&self) -> LexerConfig {
+        self.lexer
+    }
+
+    fn document_count(&self) -> usize {
+        self.doc_count
+    }
+
+    fn document(&self, index: usize) -> (&Path, usize) {
+        let (path, count) = self.document_entry(index).expect("document entries are validated");
+        (Path::new(path), count)
+    }
+
+    fn postings(&self, term: &str) -> impl ExactSizeIterator<Item = (usize, usize)> + '_ {
+        let (mut low, mut high) = (0, self.term_count);
+        let mut postings: &[u8] = &[];
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let (other, offset, len) = self.term_entry(mid).expect("term entries are validated");
+            match other.cmp(term) {
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+                Ordering::Equal => {
+                    postings = &self.bytes[offset..offset + len * POSTING_LEN];
+                    break
+                }
+            }
+        }
+        postings.chunks_exact(POSTING_LEN).map(|posting| {
+            (read_u64(posting, 0).expect("postings are validated"), read_u64(posting, 8).expect("postings are validated"))
+        })
+    }
+}
+
+/// The [`FrozenModel`] a server is searching, replaceable while it runs: an
+/// index rebuilt offline goes live with [`IndexHandle::swap`] without dropping
+/// any searches. The lock is only held to clone or replace the `Arc`, so a
+/// search in flight during a swap finishes against the old index while the
+/// following ones see the new one.
+///
+/// Searches are not lock-free as they would be with `arc-swap`, which this
+/// spares the crate from depending on: readers only ever hold the read lock
+/// for the clone of an `Arc`, never for a search, so they do not wait on each
+/// other and a swap waits at most for the clones in progress.
+pub struct IndexHandle {
+    current: RwLock<Arc<FrozenModel>>,
+}
+
+impl IndexHandle {
+    pub fn new(model: FrozenModel) -> Self {
+        Self { current: RwLock::new(Arc::new(model)) }
+    }
+
+    // Nothing can be left half done by a panic while the lock is held, since
+    // the only writes are whole replacements of the Arc, hence the poisoning
+    // is ignored.
+    pub fn current(&self) -> Arc<FrozenModel> {
+        self.current.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    pub fn search(&self, query: &[char]) -> Ranking {
+        self.current().search_query(query)
+    }
+
+    /// Makes `model` the index searched from now on, returning the previous one.
+    pub fn swap(&self, model: FrozenModel) -> Arc<FrozenModel> {
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        std::mem::replace(&mut *current, Arc::new(model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    use crate::model::{InMemoryModel, Model};
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    fn frozen(docs: &[(&str, &str)]) -> FrozenModel {
+        let mut model = InMemoryModel::default();
+        for (path, content) in docs {
+            model.add_document(PathBuf::from(path), &chars(content)).unwrap();
+        }
+        model.freeze().unwrap()
+    }
+
+    #[test]
+    fn searches_see_either_index_while_swapping() {
+        let old = [("old/a.txt", "shared apple"), ("old/b.txt", "shared banana"), ("old/c.txt", "cherry")];
+        let new = [("new/a.txt", "shared apple apple"), ("new/b.txt", "banana"), ("new/c.txt", "cherry shared")];
+        let query = chars("shared apple");
+        let (old_ranking, new_ranking) = (frozen(&old).search_query(&query), frozen(&new).search_query(&query));
+        assert_ne!(old_ranking, new_ranking);
+
+        let handle = IndexHandle::new(frozen(&old));
+        thread::scope(|scope| {
+            let searchers = (0..4).map(|_| scope.spawn(|| {
+                let mut seen = (0, 0);
+                for _ in 0..500 {
+                    let ranking = handle.search(&query);
+                    if ranking == old_ranking {
+                        seen.0 += 1;
+                    } else {
+                        assert_eq!(ranking, new_ranking);
+                        seen.1 += 1;
+                    }
+                }
+                seen
+            })).collect::<Vec<_>>();
+            for i in 0..50 {
+                handle.swap(frozen(if i % 2 == 0 { &new } else { &old }));
+            }
+            for searcher in searchers {
+                let (from_old, from_new) = searcher.join().unwrap();
+                assert_eq!(from_old + from_new, 500);
+            }
+        });
+        assert_eq!(handle.search(&query), old_ranking);
+    }
+
+    #[test]
+    fn searches_in_flight_keep_the_old_index() {
+        let handle = IndexHandle::new(frozen(&[("a.txt", "apple"), ("b.txt", "banana")]));
+        let in_flight = handle.current();
+        let previous = handle.swap(frozen(&[("c.txt", "apple"), ("d.txt", "cherry")]));
+        assert!(Arc::ptr_eq(&in_flight, &previous));
+        assert_eq!(in_flight.search_query(&chars("apple"))[0].0, PathBuf::from("a.txt"));
+        assert_eq!(handle.search(&chars("apple"))[0].0, PathBuf::from("c.txt"));
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("searust-{pid}-{name}", pid = std::process::id()))
+    }
+
+    const CORPUS: &[(&str, &str)] = &[
+        ("a.txt", "shared apple apple"),
+        ("b.txt", "shared banana"),
+        ("c.txt", "cherry shared apple"),
+        ("d.txt", "durian"),
+    ];
+
+    #[test]
+    fn mapped_index_searches_like_the_frozen_one() {
+        let model = frozen(CORPUS);
+        let path = temp_path("mapped.idx");
+        model.write_to(&path).unwrap();
+        let mapped = FrozenModel::mmap(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(mapped.document_count(), model.document_count());
+        for query in ["shared apple", "apple apple banana", "durian", "missing", ""] {
+            assert_eq!(mapped.search_query(&chars(query)), model.search_query(&chars(query)), "{query}");
+        }
+        assert_eq!(mapped.search_query(&chars("apple"))[0].0, PathBuf::from("a.txt"));
+    }
+
+    /// The bytes of the written [`CORPUS`], altered by `corrupt`, mapped again.
+    fn mmap_corrupted<F>(name: &str, corrupt: F) -> Result<MappedModel, ()> where F: FnOnce(&mut Vec<u8>) {
+        let path = temp_path(name);
+        frozen(CORPUS).write_to(&path).unwrap();
+        let mut bytes = std::fs::read(&path).unwrap();
+        corrupt(&mut bytes);
+        std::fs::write(&path, bytes).unwrap();
+        let result = FrozenModel::mmap(&path);
+        std::fs::remove_file(&path).ok();
+        result
+    }
+
+    #[test]
+    fn malformed_files_are_rejected() {
+        assert!(mmap_corrupted("empty.idx", |bytes| bytes.clear()).is_err());
+        assert!(mmap_corrupted("short-header.idx", |bytes| bytes.truncate(HEADER_LEN - 1)).is_err());
+        assert!(mmap_corrupted("half.idx", |bytes| bytes.truncate(bytes.len() / 2)).is_err());
+        assert!(mmap_corrupted("last-byte.idx", |bytes| { bytes.pop(); }).is_err());
+        assert!(mmap_corrupted("magic.idx", |bytes| bytes[0] ^= 0xff).is_err());
+        assert!(mmap_corrupted("version.idx", |bytes| bytes[MAGIC.len()] += 1).is_err());
+        assert!(mmap_corrupted("unsorted.idx", |bytes| {
+            let terms_offset = read_u64(bytes, MAGIC.len() + 6 * 8).unwrap();
+            let (first, second) = bytes[terms_offset..].split_at_mut(TERM_ENTRY_LEN);
+            first.swap_with_slice(&mut second[..TERM_ENTRY_LEN]);
+        }).is_err());
+        assert!(mmap_corrupted("intact.idx", |_| {}).is_ok());
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use super::lexer::{Lexer, LexerConfig};
+
+/// Sorts `(start, end)` spans and coalesces the overlapping and adjacent ones,
+/// so that wrapping each of the remaining spans in markers never nests them.
+pub fn merge_spans(spans: &mut Vec<(usize, usize)>) {
+    spans.sort();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+    for &(start, end) in spans.iter() {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = (*last_end).max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    *spans = merged;
+}
+
+/// Spans of the tokens of `content` that match a token of `query`, merged.
+pub fn match_spans(content: &[char], query: &[char], config: LexerConfig) -> Vec<(usize, usize)> {
+    let terms = Lexer::with_config(query, config).collect::<HashSet<_>>();
+    let mut lexer = Lexer::with_config(content, config);
+    let mut spans = Vec::new();
+    while let Some((token, span)) = lexer.next_token_with_span() {
+        if terms.contains(&token) {
+            spans.push(span);
+        }
+    }
+    merge_spans(&mut spans);
+    spans
+}
+
+/// `content` with every match of `query` wrapped in `open` and `close`.
+pub fn highlight(content: &[char], query: &[char], config: LexerConfig, open: &str, close: &str) -> String {
+    let mut result = String::new();
+    let mut last = 0;
+    for (start, end) in match_spans(content, query, config) {
+        result.extend(&content[last..start]);
+        result.push_str(open);
+        result.extend(&content[start..end]);
+        result.push_str(close);
+        last = end;
+    }
+    result.extend(&content[last..]);
+    result
+}
+
+/// Like [`highlight`], but only for the chars of `content` in `range`, e.g. the
+/// part of a large document currently on screen. Only the words around the
+/// range boundaries are tokenized beyond it, so a token is matched exactly as
+/// it would be in the whole document. A match straddling a boundary is
+/// highlighted for the part of it inside the range, and the text returned is
+/// always exactly `content[range]` plus the markers.
+pub fn highlight_range(content: &[char], query: &[char], range: Range<usize>, config: LexerConfig, open: &str, close: &str) -> String {
+    let end = range.end.min(content.len());
+    let start = range.start.min(end);
+    let mut window_start = start;
+    while window_start > 0 && content[window_start - 1].is_alphanumeric() {
+        window_start -= 1;
+    }
+    let mut window_end = end;
+    while window_end < content.len() && content[window_end].is_alphanumeric() {
+        window_end += 1;
+    }
+
+    let mut result = String::new();
+    let mut last = start;
+    for (span_start, span_end) in match_spans(&content[window_start..window_end], query, config) {
+        let span_start = (span_start + window_start).clamp(start, end);
+        let span_end = (span_end + window_start).clamp(start, end);
+        if span_start == span_end {
+            continue
+        }
+        result.extend(&content[last..span_start]);
+        result.push_str(open);
+        result.extend(&content[span_start..span_end]);
+        result.push_str(close);
+        last = span_end;
+    }
+    result.extend(&content[last..end]);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn merged_spans_are_sorted_disjoint_and_cover_the_input() {
+        // xorshift, so that the test needs no dependency and is reproducible.
+        let mut state = 0x2545f4914f6cdd1du64;
+        let mut random = |bound: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % bound as u64) as usize
+        };
+        for _ in 0..500 {
+            let mut spans = (0..random(12)).map(|_| {
+                let start = random(60);
+                (start, start + random(8))
+            }).collect::<Vec<_>>();
+            let covered = |spans: &[(usize, usize)]| {
+                (0..70).filter(|x| spans.iter().any(|(start, end)| (start..end).contains(&x))).collect::<Vec<_>>()
+            };
+            let expected = covered(&spans);
+
+            merge_spans(&mut spans);
+            assert!(spans.windows(2).all(|pair| pair[0].1 < pair[1].0), "{spans:?}");
+            assert_eq!(covered(&spans), expected, "{spans:?}");
+        }
+    }
+
+    #[test]
+    fn adjacent_matches_get_one_pair_of_markers() {
+        let config = LexerConfig::default();
+        assert_eq!(highlight(&chars("see foo::bar here"), &chars("foo : bar"), config, "[", "]"), "see [foo::bar] here");
+        assert_eq!(highlight(&chars("foo bar foo"), &chars("foo"), config, "[", "]"), "[foo] bar [foo]");
+    }
+
+    #[test]
+    fn range_highlighting_clips_matches_at_the_boundaries() {
+        let config = LexerConfig::default();
+        let content = chars("say rustacean now or never");
+        let query = chars("rustacean never");
+        let cases = [
+            (0..26, "say [rustacean] now or [never]"),
+            (2..8, "y [rust]"),
+            (10..17, "[ean] now"),
+            (6..10, "[stac]"),
+            (14..24, "now or [nev]"),
+            (13..14, " "),
+            (20..40, " [never]"),
+        ];
+        for (range, expected) in cases {
+            let highlighted = highlight_range(&content, &query, range.clone(), config, "[", "]");
+            assert_eq!(highlighted, expected, "{range:?}");
+            let end = range.end.min(content.len());
+            assert_eq!(highlighted.replace(['[', ']'], ""), content[range.start..end].iter().collect::<String>());
+        }
+        // Tokens are matched whole, so the part of a word inside the range
+        // does not match on its own.
+        assert_eq!(highlight_range(&content, &chars("rust"), 4..8, config, "[", "]"), "rust");
+    }
+}
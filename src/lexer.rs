@@ -1,10 +1,212 @@
+use serde::{Deserialize, Serialize};
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Byte order mark, also known as the zero width no-break space.
+pub const BOM: char = '\u{feff}';
+
+const STOP_WORDS: &[&str] = &[
+    "A", "AN", "AND", "ARE", "AS", "AT", "BE", "BUT", "BY", "FOR", "FROM", "HAS", "HAVE",
+    "IF", "IN", "INTO", "IS", "IT", "ITS", "NO", "NOT", "OF", "ON", "OR", "SO", "SUCH",
+    "THAT", "THE", "THEIR", "THEN", "THERE", "THESE", "THEY", "THIS", "TO", "WAS", "WERE",
+    "WILL", "WITH",
+];
+
+// Checked in order, the first matching suffix wins.
+const STEM_RULES: &[(&str, &str)] = &[
+    ("SSES", "SS"),
+    ("IES", "Y"),
+    ("CHES", "CH"),
+    ("SHES", "SH"),
+    ("XES", "X"),
+    ("ING", ""),
+    ("ED", ""),
+    ("S", ""),
+];
+
+pub fn is_stop_word(word: &str) -> bool {
+    STOP_WORDS.contains(&word.to_ascii_uppercase().as_str())
+}
+
+/// Light English suffix stripping: "indexes", "indexed" and "indexing" all
+/// become "index". Much cruder than a real stemmer but predictable.
+pub fn stem(word: &str) -> String {
+    let upper = word.to_ascii_uppercase();
+    for (suffix, replacement) in STEM_RULES {
+        if !upper.ends_with(suffix) {
+            continue
+        }
+        if *suffix == "S" && (upper.ends_with("SS") || upper.ends_with("US") || upper.ends_with("IS")) {
+            continue
+        }
+        let prefix = &upper[..upper.len() - suffix.len()];
+        if prefix.chars().count() + replacement.len() < 3 || !prefix.contains(['A', 'E', 'I', 'O', 'U', 'Y']) {
+            continue
+        }
+        let mut stem = word[..prefix.len()].to_string();
+        if replacement.is_empty() && (*suffix == "ING" || *suffix == "ED") {
+            // running -> runn -> run
+            let mut tail = prefix.chars().rev();
+            if let (Some(last), Some(before)) = (tail.next(), tail.next()) {
+                if last == before && !"AEIOULSZ".contains(last) {
+                    stem.pop();
+                }
+            }
+        }
+        if word[prefix.len()..].starts_with(|x: char| x.is_lowercase()) {
+            stem.push_str(&replacement.to_lowercase());
+        } else {
+            stem.push_str(replacement);
+        }
+        return stem
+    }
+    word.to_string()
+}
+
+/// A set of ASCII punctuation characters, see [`LexerConfig::symbols`]. Saved
+/// as the string of its characters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub struct SymbolSet(u128);
+
+impl SymbolSet {
+    /// The punctuation characters of `chars`, any others are left out.
+    pub fn new(chars: &str) -> Self {
+        Self(chars.chars().filter(|x| x.is_ascii_punctuation()).fold(0, |set, x| set | 1 << x as u32))
+    }
+
+    pub fn contains(&self, x: char) -> bool {
+        x.is_ascii() && self.0 & 1 << x as u32 != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl From<String> for SymbolSet {
+    fn from(chars: String) -> Self {
+        Self::new(&chars)
+    }
+}
+
+impl From<SymbolSet> for String {
+    fn from(set: SymbolSet) -> Self {
+        (0..128u8).map(char::from).filter(|x| set.contains(*x)).collect()
+    }
+}
+
+/// Named combinations of [`LexerConfig`] options for common kinds of content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    /// Natural language: stemming and stop words, no punctuation tokens.
+    Prose,
+    /// Source code: identifiers split at camelCase boundaries, no stemming.
+    Code,
+    /// Log lines: words and numbers such as ids and status codes kept whole,
+    /// no stemming or stop words.
+    Logs,
+    /// File names and paths: split into their words, extensions and numbers,
+    /// so "IMG_2041.jpeg" and "report2023-final.pdf" are found by "2041",
+    /// "jpeg", "report" or "2023".
+    Filenames,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct LexerConfig {
+    /// Fold letters to upper case so matching is case-insensitive.
+    pub fold_case: bool,
+    /// Strip diacritics so "café" and "cafe" are the same term. Off by default
+    /// since some languages treat accented letters as distinct.
+    pub ascii_fold: bool,
+    /// Split words at camelCase boundaries: "parseHTTPRequest" becomes
+    /// "parse", "HTTP" and "Request".
+    pub split_identifiers: bool,
+    /// Split words where letters and digits meet: "report2023" becomes
+    /// "report" and "2023".
+    pub split_numbers: bool,
+    /// Reduce words to their stems, see [`stem`].
+    pub stem: bool,
+    /// Skip common English function words, see [`is_stop_word`].
+    pub stop_words: bool,
+    /// Emit runs of digits as tokens.
+    pub numbers: bool,
+    /// Emit any other non-whitespace character as a token of its own.
+    pub punctuation: bool,
+    /// Emit runs of these characters as tokens, e.g. "::" and "->" in code,
+    /// whether or not other punctuation is emitted. Empty by default.
+    pub symbols: SymbolSet,
+}
+
+impl Default for LexerConfig {
+    fn default() -> Self {
+        Self {
+            fold_case: true,
+            ascii_fold: false,
+            split_identifiers: false,
+            split_numbers: false,
+            stem: false,
+            stop_words: false,
+            numbers: true,
+            punctuation: true,
+            symbols: SymbolSet::default(),
+        }
+    }
+}
+
+impl From<Preset> for LexerConfig {
+    fn from(preset: Preset) -> Self {
+        let base = Self { punctuation: false, ..Self::default() };
+        match preset {
+            Preset::Prose => Self { stem: true, stop_words: true, ..base },
+            Preset::Code => Self { split_identifiers: true, ..base },
+            Preset::Logs => base,
+            Preset::Filenames => Self { split_identifiers: true, split_numbers: true, ..base },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DropReason {
+    StopWord,
+    Number,
+    Punctuation,
+}
+
+/// A piece of input as seen by [`Lexer::next_traced`], with the `(start, end)`
+/// range of chars it was made of.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Traced {
+    /// Emitted as `token`. `unstemmed` is the word before stemming, if that
+    /// changed it.
+    Token { token: String, span: (usize, usize), unstemmed: Option<String> },
+    /// Filtered out by the configuration.
+    Dropped { text: String, reason: DropReason, span: (usize, usize) },
+}
+
 pub struct Lexer<'a> {
     content: &'a [char],
+    len: usize,
+    config: LexerConfig,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(content: &'a [char]) -> Self {
-        Self { content }
+        Self::with_config(content, LexerConfig::default())
+    }
+
+    pub fn with_config(content: &'a [char], config: LexerConfig) -> Self {
+        let len = content.len();
+        let mut this = Self { content, len, config };
+        while let [BOM, rest @ ..] = this.content {
+            this.content = rest;
+        }
+        this
+    }
+
+    fn offset(&self) -> usize {
+        self.len - self.content.len()
     }
 
     fn trim_left(&mut self) {
@@ -27,21 +229,103 @@ impl<'a> Lexer<'a> {
         self.chop(n)
     }
 
+    fn is_word_char(&self, x: char) -> bool {
+        if self.config.split_numbers {
+            x.is_alphabetic()
+        } else {
+            x.is_alphanumeric()
+        }
+    }
+
+    fn chop_identifier_part(&mut self) -> &'a [char] {
+        let mut n = 1;
+        while n < self.content.len() && self.is_word_char(self.content[n]) {
+            let (prev, cur) = (self.content[n - 1], self.content[n]);
+            let next_is_lower = self.content.get(n + 1).is_some_and(|x| x.is_lowercase());
+            if cur.is_uppercase() && (!prev.is_uppercase() || next_is_lower) {
+                break
+            }
+            n += 1;
+        }
+        self.chop(n)
+    }
+
+    fn fold_case(&self, word: &[char]) -> String {
+        let word = if self.config.ascii_fold {
+            word.iter().copied().nfd().filter(|x| !is_combining_mark(*x)).collect()
+        } else {
+            word.to_vec()
+        };
+        if self.config.fold_case {
+            word.iter().map(|x| x.to_ascii_uppercase()).collect()
+        } else {
+            word.iter().collect()
+        }
+    }
+
     pub fn next_token(&mut self) -> Option<String> {
+        self.next_token_with_span().map(|(token, _)| token)
+    }
+
+    /// Next token along with the `(start, end)` range of chars it was made of.
+    pub fn next_token_with_span(&mut self) -> Option<(String, (usize, usize))> {
+        loop {
+            if let Traced::Token { token, span, .. } = self.next_traced()? {
+                return Some((token, span))
+            }
+        }
+    }
+
+    /// Like [`Lexer::next_token_with_span`], but also reports the input that
+    /// the configuration filters out instead of silently skipping it.
+    pub fn next_traced(&mut self) -> Option<Traced> {
         self.trim_left();
         if self.content.is_empty() {
             return None
         }
+        let start = self.offset();
 
         if self.content[0].is_numeric() {
-            return Some(self.chop_while(|x| x.is_numeric()).iter().collect());
+            let token = self.chop_while(|x| x.is_numeric()).iter().collect();
+            let span = (start, self.offset());
+            if !self.config.numbers {
+                return Some(Traced::Dropped { text: token, reason: DropReason::Number, span })
+            }
+            return Some(Traced::Token { token, span, unstemmed: None })
         }
 
         if self.content[0].is_alphabetic() {
-            return Some(self.chop_while(|x| x.is_alphanumeric()).iter().map(|x| x.to_ascii_uppercase()).collect());
+            let word = if self.config.split_identifiers {
+                self.chop_identifier_part()
+            } else {
+                let split_numbers = self.config.split_numbers;
+                self.chop_while(|x| if split_numbers { x.is_alphabetic() } else { x.is_alphanumeric() })
+            };
+            let word = self.fold_case(word);
+            let span = (start, self.offset());
+            if self.config.stop_words && is_stop_word(&word) {
+                return Some(Traced::Dropped { text: word, reason: DropReason::StopWord, span })
+            }
+            if self.config.stem {
+                let token = stem(&word);
+                let unstemmed = (token != word).then_some(word);
+                return Some(Traced::Token { token, span, unstemmed })
+            }
+            return Some(Traced::Token { token: word, span, unstemmed: None })
+        }
+
+        if self.config.symbols.contains(self.content[0]) {
+            let symbols = self.config.symbols;
+            let token = self.chop_while(|x| symbols.contains(*x)).iter().collect();
+            return Some(Traced::Token { token, span: (start, self.offset()), unstemmed: None })
         }
 
-        return Some(self.chop(1).iter().collect());
+        let token = self.chop(1).iter().collect();
+        let span = (start, self.offset());
+        if !self.config.punctuation {
+            return Some(Traced::Dropped { text: token, reason: DropReason::Punctuation, span })
+        }
+        Some(Traced::Token { token, span, unstemmed: None })
     }
 }
 
@@ -51,4 +335,77 @@ impl<'a> Iterator for Lexer<'a> {
     fn next(&mut self) -> Option<Self::Item> {
         self.next_token()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    use crate::model::{InMemoryModel, Model};
+
+    fn tokens(content: &str, config: LexerConfig) -> Vec<String> {
+        Lexer::with_config(&content.chars().collect::<Vec<_>>(), config).collect()
+    }
+
+    #[test]
+    fn prose_stems_and_drops_stop_words() {
+        assert_eq!(tokens("The indexes are running, quickly!", Preset::Prose.into()), ["INDEX", "RUN", "QUICKLY"]);
+    }
+
+    #[test]
+    fn code_splits_identifiers_without_stemming() {
+        assert_eq!(tokens("parseHTTPRequest(utf8) -> parsing", Preset::Code.into()),
+                   ["PARSE", "HTTP", "REQUEST", "UTF8", "PARSING"]);
+    }
+
+    #[test]
+    fn logs_keep_words_and_numbers_whole() {
+        assert_eq!(tokens("ERROR [2023-01-05] the connectionId 42 refused: status=503", Preset::Logs.into()),
+                   ["ERROR", "2023", "01", "05", "THE", "CONNECTIONID", "42", "REFUSED", "STATUS", "503"]);
+    }
+
+    #[test]
+    fn filenames_split_words_extensions_and_numbers() {
+        let config = Preset::Filenames.into();
+        assert_eq!(tokens("IMG_2041.jpeg", config), ["IMG", "2041", "JPEG"]);
+        assert_eq!(tokens("report2023-final.pdf", config), ["REPORT", "2023", "FINAL", "PDF"]);
+        assert_eq!(tokens("src/myPhotos.tar.gz", config), ["SRC", "MY", "PHOTOS", "TAR", "GZ"]);
+        assert_ne!(tokens("report2023-final.pdf", Preset::Code.into()), tokens("report2023-final.pdf", config));
+    }
+
+    #[test]
+    fn presets_can_be_overridden() {
+        let config = LexerConfig { fold_case: false, ..Preset::Code.into() };
+        assert_eq!(tokens("parseHTTPRequest", config), ["parse", "HTTP", "Request"]);
+    }
+
+    #[test]
+    fn symbols_are_searchable_terms() {
+        let config = LexerConfig { symbols: SymbolSet::new(":->"), ..LexerConfig::default() };
+        assert_eq!(tokens("foo::bar", config), ["FOO", "::", "BAR"]);
+        assert_eq!(tokens("a -> b", config), ["A", "->", "B"]);
+        assert_eq!(tokens("foo::bar", LexerConfig::default()), ["FOO", ":", ":", "BAR"]);
+
+        let mut model = InMemoryModel::default().with_lexer(config);
+        for (path, content) in [("path.rs", "foo::bar"), ("arrow.rs", "a -> b"), ("plain.rs", "foo bar a b")] {
+            model.add_document(PathBuf::from(path), &content.chars().collect::<Vec<_>>()).unwrap();
+        }
+        for (query, path) in [("::", "path.rs"), ("->", "arrow.rs")] {
+            let ranking = model.search_query(&query.chars().collect::<Vec<_>>()).unwrap();
+            assert_eq!(ranking.iter().map(|(path, _)| path.to_str().unwrap()).collect::<Vec<_>>(), [path], "{query}");
+        }
+    }
+
+    #[test]
+    fn symbol_sets_round_trip_through_serde() {
+        let set = SymbolSet::new(":->$!=");
+        let json = serde_json::to_string(&set).unwrap();
+        assert_eq!(json, "\"!$-:=>\"");
+        assert_eq!(serde_json::from_str::<SymbolSet>(&json).unwrap(), set);
+        let config = LexerConfig { symbols: set, ..LexerConfig::default() };
+        assert_eq!(serde_json::from_str::<LexerConfig>(&serde_json::to_string(&config).unwrap()).unwrap(), config);
+        // Characters that are not punctuation are left out.
+        assert_eq!(SymbolSet::new("a:é"), SymbolSet::new(":"));
+    }
+}
@@ -0,0 +1,15 @@
+// Errors are reported to stderr at the point of failure, so fallible functions
+// return Result<_, ()> throughout the crate.
+#![allow(clippy::result_unit_err)]
+
+#[cfg(feature = "async")]
+pub mod async_model;
+pub mod blend;
+pub mod frozen;
+pub mod highlight;
+pub mod lexer;
+pub mod model;
+pub mod parse;
+pub mod query;
+pub mod recording;
+pub mod server;
@@ -4,61 +4,12 @@ use std::env;
 use std::result::Result;
 use std::process::ExitCode;
 use std::str;
-use std::io::{BufReader, BufWriter};
+use std::io::BufWriter;
 
-use xml::reader::{XmlEvent, EventReader};
-use xml::common::{Position, TextPosition};
+use searust::model::*;
+use searust::parse::{add_folder_to_model, IndexOptions, IndexStats};
+use searust::server;
 
-mod model;
-use model::*;
-mod server;
-mod lexer;
-
-
-fn parse_entire_txt_file(file_path: &Path) -> Result<String, ()> {
-    fs::read_to_string(file_path).map_err(|err| {
-        eprintln!("ERROR: coult not open file {file_path}: {err}", file_path = file_path.display());
-    })
-}
-
-fn parse_entire_file_by_extension(file_path: &Path) -> Result<String, ()> {
-    let extension = file_path.extension().ok_or_else(|| {
-        eprintln!("ERROR: can't detect file type of {file_path} without extension",
-                  file_path = file_path.display());
-    })?.to_string_lossy();
-    match extension.as_ref() {
-        "xhtml" | "xml" => parse_entire_xml_file(file_path),
-        // TODO: specialized parser for markdown files
-        "txt" | "md" => parse_entire_txt_file(file_path),
-        _ => {
-            eprintln!("ERROR: can't detect file type of {file_path}: unsupported extension {extension}",
-                      file_path = file_path.display(),
-                      extension = extension);
-            Err(())
-        }
-    }
-}
-
-fn parse_entire_xml_file(file_path: &Path) -> Result<String, ()> {
-    let file = File::open(file_path).map_err(|err| {
-        eprintln!("ERROR: could not open file {file_path}: {err}", file_path = file_path.display());
-    })?;
-    let er = EventReader::new(BufReader::new(file));
-    let mut content = String::new();
-    for event in er.into_iter() {
-        let event = event.map_err(|err| {
-            let TextPosition {row, column} = err.position();
-            let msg = err.msg();
-            eprintln!("{file_path}:{row}:{column}: ERROR: {msg}", file_path = file_path.display());
-        })?;
-
-        if let XmlEvent::Characters(text) = event {
-            content.push_str(&text);
-            content.push(' ');
-        }
-    }
-    Ok(content)
-}
 
 fn save_model_as_json(model: &InMemoryModel, index_path: &str) -> Result<(), ()> {
     println!("Saving {index_path}...");
@@ -74,48 +25,6 @@ fn save_model_as_json(model: &InMemoryModel, index_path: &str) -> Result<(), ()>
     Ok(())
 }
 
-fn add_folder_to_model(dir_path: &Path, model: &mut dyn Model, skipped: &mut usize) -> Result<(), ()> {
-    let dir = fs::read_dir(dir_path).map_err(|err| {
-        eprintln!("ERROR: could not open directory {dir_path} for indexing: {err}",
-                  dir_path = dir_path.display());
-    })?;
-
-    'next_file: for file in dir {
-        let file = file.map_err(|err| {
-            eprintln!("ERROR: could not read next file in directory {dir_path} during indexing: {err}",
-                      dir_path = dir_path.display());
-        })?;
-
-        let file_path = file.path();
-
-        let file_type = file.file_type().map_err(|err| {
-            eprintln!("ERROR: could not determine type of file {file_path}: {err}",
-                      file_path = file_path.display());
-        })?;
-
-        if file_type.is_dir() {
-            add_folder_to_model(&file_path, model, skipped)?;
-            continue 'next_file;
-        }
-
-        // TODO: how does this work with symlinks?
-
-        println!("Indexing {:?}...", &file_path);
-
-        let content = match parse_entire_file_by_extension(&file_path) {
-            Ok(content) => content.chars().collect::<Vec<_>>(),
-            Err(()) => {
-                *skipped += 1;
-                continue 'next_file;
-            }
-        };
-
-        model.add_document(file_path, &content)?;
-    }
-
-    Ok(())
-}
-
 fn usage(program: &str) {
     eprintln!("Usage: {program} [SUBCOMMAND] [OPTIONS]");
     eprintln!("Subcommands:");
@@ -131,7 +40,7 @@ fn entry() -> Result<(), ()> {
     let mut subcommand = None;
     let mut use_sqlite_mode = false;
 
-    while let Some(arg) = args.next() {
+    for arg in args.by_ref() {
         match arg.as_str() {
             "--sqlite" => use_sqlite_mode = true,
             _ => {
@@ -153,7 +62,8 @@ fn entry() -> Result<(), ()> {
                 eprintln!("ERROR: no directory is provided for {subcommand} subcommand");
             })?;
 
-            let mut skipped = 0;
+            let opts = IndexOptions { verbose: true };
+            let mut stats = IndexStats::default();
 
             if use_sqlite_mode {
                 let index_path = "index.db";
@@ -165,18 +75,18 @@ fn entry() -> Result<(), ()> {
                     }
                 }
 
-                let mut model = SqliteModel::open(Path::new(index_path))?;
+                let mut model = SqliteModel::open(Path::new(index_path)).map_err(|_| ())?;
                 model.begin()?;
-                add_folder_to_model(Path::new(&dir_path), &mut model, &mut skipped)?;
+                add_folder_to_model(Path::new(&dir_path), &mut model, &opts, &mut stats)?;
                 model.commit()?;
             } else {
                 let index_path = "index.json";
                 let mut model = Default::default();
-                add_folder_to_model(Path::new(&dir_path), &mut model, &mut skipped)?;
+                add_folder_to_model(Path::new(&dir_path), &mut model, &opts, &mut stats)?;
                 save_model_as_json(&model, index_path)?;
             }
 
-            println!("Skipped {skipped} files.");
+            println!("Skipped {skipped} files.", skipped = stats.skipped);
             Ok(())
         },
         "search" => {
@@ -191,19 +101,12 @@ fn entry() -> Result<(), ()> {
             })?.chars().collect::<Vec<_>>();
 
             if use_sqlite_mode {
-                let model = SqliteModel::open(Path::new(&index_path))?;
+                let model = SqliteModel::open(Path::new(&index_path)).map_err(|_| ())?;
                 for (path, rank) in model.search_query(&prompt)?.iter().take(20) {
                     println!("{path} {rank}", path = path.display());
                 }
             } else {
-                let index_file = File::open(&index_path).map_err(|err| {
-                    eprintln!("ERROR: could not open index file {index_path}: {err}");
-                })?;
-
-                let model = serde_json::from_reader::<_, InMemoryModel>(index_file).map_err(|err| {
-                    eprintln!("ERROR: could not parse index file {index_path}: {err}");
-                })?;
-
+                let model = InMemoryModel::load(Path::new(&index_path), None)?;
                 for (path, rank) in model.search_query(&prompt)?.iter().take(20) {
                     println!("{path} {rank}", path = path.display());
                 }
@@ -219,17 +122,10 @@ fn entry() -> Result<(), ()> {
             let address = args.next().unwrap_or("127.0.0.1:6969".to_string());
  
             if use_sqlite_mode {
-                let model = SqliteModel::open(Path::new(&index_path))?;
+                let model = SqliteModel::open(Path::new(&index_path)).map_err(|_| ())?;
                 server::start(&address, &model)
             } else {
-                let index_file = File::open(&index_path).map_err(|err| {
-                    eprintln!("ERROR: could not open index file {index_path}: {err}");
-                })?;
-
-                let model: InMemoryModel = serde_json::from_reader(index_file).map_err(|err| {
-                    eprintln!("ERROR: could not parse index file {index_path}: {err}");
-                })?;
-
+                let model = InMemoryModel::load(Path::new(&index_path), None)?;
                 server::start(&address, &model)
             }
         },
@@ -1,234 +1,4120 @@
-use std::collections::HashMap;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufReader, ErrorKind};
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::thread;
 use std::result::Result;
 
-use super::lexer::Lexer;
+use super::blend::{BlendedScorer, DocumentStats};
+use super::frozen::FrozenModel;
+use super::lexer::{BOM, DropReason, Lexer, LexerConfig, Preset, Traced};
+use super::parse::{add_folder_to_model, IndexOptions, IndexStats};
+use super::query::Query;
 
 use serde::{Deserialize, Serialize};
 
 pub trait Model {
     fn search_query(&self, query: &[char]) -> Result<Vec<(PathBuf, f32)>, ()>;
     fn add_document(&mut self, file_path: PathBuf, content: &[char]) -> Result<(), ()>;
+    /// Number of postings the query would visit, i.e. the sum of the document
+    /// frequencies of its terms. Cheap enough to call before every search.
+    fn estimate_cost(&self, query: &[char]) -> Result<usize, ()>;
+    /// Removes the documents at `paths` from the index in one go, returning how
+    /// many of them were actually indexed.
+    fn remove_documents(&mut self, paths: &[PathBuf]) -> Result<usize, ()>;
+    /// Number of indexed documents, to tell an empty index apart from a query
+    /// that matched nothing.
+    fn document_count(&self) -> Result<usize, ()>;
+    /// The `n` terms that occur in the most documents, most common first, e.g.
+    /// to build a stop word list tailored to the corpus.
+    fn most_frequent_terms(&self, n: usize) -> Result<Vec<(String, usize)>, ()>;
+    fn remove_document(&mut self, path: &Path) -> Result<bool, ()> {
+        self.remove_documents(&[path.to_path_buf()]).map(|removed| removed > 0)
+    }
+    /// Reclaims the space left behind by removed documents in the table of
+    /// document paths. Backends that need no such upkeep do nothing.
+    fn compact_paths(&mut self) -> Result<(), ()> {
+        Ok(())
+    }
+    /// Loads whatever searching `queries` needs into the caches, e.g. those of
+    /// a query log replayed after a restart, so that the first real searches
+    /// are not slowed down by a cold start. Backends without caches to fill do
+    /// nothing.
+    fn prewarm_from_queries(&self, _queries: &[Vec<char>]) -> Result<(), ()> {
+        Ok(())
+    }
 }
 
 pub type DocFreq = HashMap<String, usize>;
 pub type TermFreq = HashMap::<String, usize>;
+pub type Ranking = Vec<(PathBuf, f32)>;
+
+pub type TermPositions = HashMap<String, Vec<usize>>;
 
 #[derive(Default, Deserialize, Serialize)]
 struct Doc {
     tf: TermFreq,
     count: usize,
+    #[serde(default)]
+    positions: TermPositions,
+    /// The same statistics per field, for documents added with
+    /// [`InMemoryModel::add_document_with_fields`].
+    #[serde(default)]
+    fields: HashMap<String, Doc>,
+    /// Whether terms past [`InMemoryModel::with_max_document_terms`] were cut off.
+    #[serde(default)]
+    truncated: bool,
+    /// Groups allowed to see the document, see [`InMemoryModel::set_document_groups`].
+    #[serde(default)]
+    groups: BTreeSet<GroupId>,
+    /// See [`InMemoryModel::update_document_versioned`].
+    #[serde(default)]
+    version: Option<u64>,
+}
+
+/// Keeps only the first `max` terms of `doc`, returning whether any were cut.
+fn truncate_doc(doc: &mut Doc, max: usize) -> bool {
+    if doc.count <= max {
+        return false
+    }
+    let mut terms = doc.positions.drain().flat_map(|(term, positions)| {
+        positions.into_iter().map(move |position| (position, term.clone()))
+    }).collect::<Vec<_>>();
+    terms.sort();
+    terms.truncate(max);
+    doc.tf.clear();
+    for (position, term) in terms {
+        *doc.tf.entry(term.clone()).or_default() += 1;
+        doc.positions.entry(term).or_default().push(position);
+    }
+    doc.count = max;
+    doc.truncated = true;
+    true
 }
-type Docs = HashMap<PathBuf, Doc>;
+
+type Docs = HashMap<DocId, Doc>;
+/// Index of the path of a document in [`InMemoryModel::paths`].
+type DocId = u32;
+
+/// A group of users given access to documents, see
+/// [`InMemoryModel::search_query_acl`].
+pub type GroupId = u32;
+type Postings = HashMap<String, BTreeSet<DocId>>;
 
 fn compute_tf(t: &str, doc: &Doc) -> f32 {
+    if doc.count == 0 {
+        return 0.0
+    }
     let n = doc.count as f32;
     let m = doc.tf.get(t).cloned().unwrap_or(0) as f32;
     m / n
 }
 
+/// How much an occurrence of a term counts towards its frequency in a
+/// document, depending on its position.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Deserialize, Serialize)]
+pub enum PositionWeighting {
+    /// Every occurrence counts as one.
+    #[default]
+    Uniform,
+    /// An occurrence counts half as much every `half_life` tokens into the
+    /// document, favouring titles and introductions.
+    Decay { half_life: usize },
+    /// An occurrence within the first `positions` tokens counts as `boost`,
+    /// since the opening of a document is usually its most topical part.
+    Lead { positions: usize, boost: f32 },
+}
+
+fn compute_weighted_tf(t: &str, doc: &Doc, weighting: PositionWeighting) -> f32 {
+    match (weighting, doc.positions.get(t)) {
+        (PositionWeighting::Decay { half_life }, Some(positions)) if half_life > 0 => {
+            let m = positions.iter().map(|position| 0.5f32.powf(*position as f32 / half_life as f32)).sum::<f32>();
+            m / doc.count as f32
+        }
+        (PositionWeighting::Lead { positions: lead, boost }, Some(positions)) => {
+            let m = positions.iter().map(|position| if *position < lead { boost } else { 1.0 }).sum::<f32>();
+            m / doc.count as f32
+        }
+        _ => compute_tf(t, doc),
+    }
+}
+
+fn cosine_similarity(a: &HashMap<String, f32>, b: &HashMap<String, f32>) -> f32 {
+    let dot = a.iter().map(|(term, x)| x * b.get(term).cloned().unwrap_or(0.0)).sum::<f32>();
+    let norm_a = a.values().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.values().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0
+    }
+    dot / (norm_a * norm_b)
+}
+
+fn merge_doc_terms(doc: &mut Doc, from: &str, into: &str) {
+    if let Some(freq) = doc.tf.remove(from) {
+        *doc.tf.entry(into.to_string()).or_default() += freq;
+    }
+    if let Some(positions) = doc.positions.remove(from) {
+        let merged = doc.positions.entry(into.to_string()).or_default();
+        merged.extend(positions);
+        merged.sort();
+    }
+    for field in doc.fields.values_mut() {
+        merge_doc_terms(field, from, into);
+    }
+}
+
+fn remove_doc_term(doc: &mut Doc, term: &str) {
+    doc.tf.remove(term);
+    doc.positions.remove(term);
+    for field in doc.fields.values_mut() {
+        remove_doc_term(field, term);
+    }
+}
+
+fn flatten_json(value: &serde_json::Value, text: &mut String) {
+    match value {
+        serde_json::Value::Null => {}
+        serde_json::Value::String(string) => {
+            text.push_str(string);
+            text.push(' ');
+        }
+        serde_json::Value::Array(values) => values.iter().for_each(|value| flatten_json(value, text)),
+        serde_json::Value::Object(values) => values.values().for_each(|value| flatten_json(value, text)),
+        value => {
+            text.push_str(&value.to_string());
+            text.push(' ');
+        }
+    }
+}
+
+/// Tokens of `lexer` along with their positions. Stop words dropped by the
+/// lexer still take up a position, so that "king of england" is stored with a
+/// gap between "king" and "england" and the phrase still matches it.
+fn positioned_terms(mut lexer: Lexer) -> Vec<(usize, String)> {
+    let mut terms = Vec::new();
+    let mut position = 0;
+    while let Some(traced) = lexer.next_traced() {
+        match traced {
+            Traced::Token { token, .. } => {
+                terms.push((position, token));
+                position += 1;
+            }
+            Traced::Dropped { reason: DropReason::StopWord, .. } => position += 1,
+            Traced::Dropped { .. } => {}
+        }
+    }
+    terms
+}
+
+fn contains_phrase(terms: &[(usize, String)], doc: &Doc) -> bool {
+    let Some(((origin, first), rest)) = terms.split_first() else {
+        return false
+    };
+    let Some(starts) = doc.positions.get(first) else {
+        return false
+    };
+    starts.iter().any(|start| {
+        rest.iter().all(|(position, term)| {
+            doc.positions.get(term).is_some_and(|ps| ps.binary_search(&(start + position - origin)).is_ok())
+        })
+    })
+}
+
 fn compute_idf(t: &str, n: usize, df: &DocFreq) -> f32 {
+    if n == 0 {
+        return 0.0
+    }
     let n = n as f32;
     let m = df.get(t).cloned().unwrap_or(1) as f32;
     (n / m).log10()
 }
 
+fn contains_any(terms: &[(String, f32)], doc: &Doc) -> bool {
+    terms.iter().any(|(term, _)| doc.tf.contains_key(term))
+}
+
+fn compute_jaccard(terms: &[(String, f32)], doc: &Doc) -> f32 {
+    let terms = terms.iter().map(|(term, _)| term).collect::<HashSet<_>>();
+    let intersection = terms.iter().filter(|term| doc.tf.contains_key(term.as_str())).count();
+    let union = terms.len() + doc.tf.len() - intersection;
+    if union == 0 {
+        return 0.0
+    }
+    intersection as f32 / union as f32
+}
+
+
+fn count_terms<I>(terms: I) -> (TermFreq, usize) where I: IntoIterator<Item = String> {
+    let mut tf = TermFreq::new();
+    let mut count = 0;
+    for term in terms {
+        if let Some(freq) = tf.get_mut(&term) {
+            *freq += 1;
+        } else {
+            tf.insert(term, 1);
+        }
+        count += 1;
+    }
+    (tf, count)
+}
+
+/// Frequencies of the terms of `content` under the default [`Lexer`] along
+/// with their total count, exactly as a model using it would store them.
+pub fn term_frequencies(content: &[char]) -> (TermFreq, usize) {
+    count_terms(Lexer::new(content))
+}
+
+/// Orders `candidates` by TF-IDF relevance to `query` without building an
+/// index, returning their indices with the scores. Document frequencies are
+/// counted over the candidates themselves unless `df` provides them along
+/// with the number of documents they were counted over.
+pub fn rerank(query: &[char], candidates: &[Vec<char>], df: Option<(&DocFreq, usize)>) -> Vec<(usize, f32)> {
+    let docs = candidates.iter().map(|content| {
+        let (tf, count) = term_frequencies(content);
+        Doc { tf, count, ..Doc::default() }
+    }).collect::<Vec<_>>();
+
+    let own_df;
+    let (df, n) = match df {
+        Some(df) => df,
+        None => {
+            let mut df = DocFreq::new();
+            for term in docs.iter().flat_map(|doc| doc.tf.keys()) {
+                *df.entry(term.clone()).or_default() += 1;
+            }
+            own_df = df;
+            (&own_df, docs.len())
+        }
+    };
+
+    let terms = Lexer::new(query).collect::<Vec<_>>();
+    let mut result = docs.iter().enumerate().map(|(i, doc)| {
+        (i, terms.iter().map(|term| compute_tf(term, doc) * compute_idf(term, n, df)).sum::<f32>())
+    }).collect::<Vec<_>>();
+    result.sort_by(|(_, rank1), (_, rank2)| rank2.total_cmp(rank1));
+    result
+}
+
+/// A document found by [`InMemoryModel::search_results`], with what a result
+/// page may want to show about it besides its score.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchResult {
+    pub path: PathBuf,
+    pub score: f32,
+    /// Length of the document in terms.
+    pub term_count: usize,
+    /// Number of distinct query terms found in the document.
+    pub matched_terms: usize,
+    /// Raw counts behind the score, see [`InMemoryModel::with_debug_results`].
+    pub debug: Option<ResultDebug>,
+}
+
+/// Raw counts the TF of a [`SearchResult`] was computed from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResultDebug {
+    /// Number of occurrences in the document of every query term found in it.
+    pub term_frequencies: HashMap<String, usize>,
+    /// Length of the document in terms.
+    pub term_count: usize,
+}
+
+/// How much a query term contributed to the score of a document, see
+/// [`InMemoryModel::explain`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TermContribution {
+    pub term: String,
+    /// Sum of the weights of the occurrences of the term in the query.
+    pub weight: f32,
+    pub boost: f32,
+    pub tf: f32,
+    pub idf: f32,
+    /// `weight * boost * tf * idf`
+    pub contribution: f32,
+}
+
+/// Why a document got its score for a query: the contributions of the query
+/// terms add up to `score`, up to the rounding set with
+/// [`InMemoryModel::with_score_rounding`].
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Explanation {
+    pub path: PathBuf,
+    pub score: f32,
+    pub terms: Vec<TermContribution>,
+}
+
+/// Everything indexed about a document, see [`InMemoryModel::document_debug_json`].
+#[derive(Serialize)]
+struct DocumentDump<'a> {
+    path: &'a Path,
+    id: DocId,
+    term_count: usize,
+    truncated: bool,
+    version: Option<u64>,
+    groups: &'a BTreeSet<GroupId>,
+    /// Euclidean norm of the TF-IDF vector, as used for similarity.
+    norm: f32,
+    term_frequencies: BTreeMap<&'a str, usize>,
+    positions: BTreeMap<&'a str, &'a [usize]>,
+    /// Boosts set with [`InMemoryModel::with_term_boosts`] for the terms of
+    /// the document.
+    term_boosts: BTreeMap<&'a str, f32>,
+    fields: BTreeMap<&'a str, FieldDump<'a>>,
+}
+
+#[derive(Serialize)]
+struct FieldDump<'a> {
+    term_count: usize,
+    term_frequencies: BTreeMap<&'a str, usize>,
+}
+
+/// What [`Model::add_document`] would store for some content, along with what
+/// the lexer dropped or stemmed on the way. See [`InMemoryModel::preview_document`].
+#[derive(Debug, Default)]
+pub struct DocumentPreview {
+    pub tf: TermFreq,
+    pub count: usize,
+    pub dropped: Vec<(String, DropReason)>,
+    /// Words changed by stemming, paired with their stems.
+    pub stemmed: Vec<(String, String)>,
+}
+
+// Multiplier applied to the contribution of terms matched by the substring
+// fallback, so they never outrank what an exact match would have scored.
+const SUBSTRING_PENALTY: f32 = 0.5;
+
+/// How a raw score is mapped to a confidence in [0, 1].
+#[derive(Clone, Copy, Default, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Calibration {
+    /// Score relative to the best any document could do for the query: one made
+    /// up solely of the query terms, each of them found nowhere else in the corpus.
+    #[default]
+    MaxScore,
+    /// `1 / (1 + e^(-steepness * (score - midpoint)))`
+    Sigmoid { midpoint: f32, steepness: f32 },
+}
+
+/// Formula used to rank documents against a query.
+#[derive(Clone, Copy, Default, Debug, PartialEq, Deserialize, Serialize)]
+pub enum Scorer {
+    /// Sum of `qtf * tf * idf` over the query terms, where `qtf` is how many
+    /// times the term occurs in the query, so repeating a term in the query
+    /// gives it proportionally more weight. See
+    /// [`InMemoryModel::with_query_term_frequency`] to count it once instead.
+    #[default]
+    TfIdf,
+    /// Overlap between the sets of query and document terms, ignoring
+    /// frequencies. Suits short categorical queries such as tag matching.
+    Jaccard,
+}
+
+/// How the scores of the fields of a document are combined into one, see
+/// [`InMemoryModel::with_field_fusion`].
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub enum FieldFusion {
+    /// Sum of the weighted field scores.
+    WeightedSum,
+    /// Best weighted field score, for when a strong match in any single field
+    /// makes a document relevant.
+    Max,
+    /// Sum of `weight / (60 + rank)` over the fields, where `rank` is the
+    /// position of the document when ranking all documents by that field alone.
+    /// Insensitive to how differently the fields' scores are scaled.
+    ReciprocalRank,
+}
+
+/// How [`InMemoryModel::score_topic`] combines the scores of a document for
+/// the queries making up a topic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TopicCombine {
+    /// Its best score, for topics matched by any of the queries.
+    Max,
+    /// The average of its scores, for topics matched by all of them together.
+    Mean,
+}
+
+// Constant of reciprocal rank fusion damping the influence of the top ranks,
+// 60 as in the paper introducing it.
+const RRF_K: f32 = 60.0;
+
+/// What to do with query terms shorter than the configured minimum length,
+/// see [`InMemoryModel::with_min_query_term_len`].
+#[derive(Clone, Copy, Default, Debug, PartialEq, Deserialize, Serialize)]
+pub enum ShortTermPolicy {
+    /// Ignore them.
+    #[default]
+    Drop,
+    /// Match every indexed term starting with them instead.
+    ExpandPrefix,
+}
+
+/// What to do with queries of more terms than the configured maximum, see
+/// [`InMemoryModel::with_max_query_terms`].
+#[derive(Clone, Copy, Default, Debug, PartialEq, Deserialize, Serialize)]
+pub enum QueryTermOverflow {
+    /// Search with the first terms only.
+    #[default]
+    Truncate,
+    /// Refuse to search.
+    Error,
+}
+
+/// Default maximum number of query terms, far more than anyone types but few
+/// enough to keep the cost of a search bounded.
+pub const DEFAULT_MAX_QUERY_TERMS: usize = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+struct MaxQueryTerms(usize);
+
+impl Default for MaxQueryTerms {
+    fn default() -> Self {
+        Self(DEFAULT_MAX_QUERY_TERMS)
+    }
+}
+
+/// Tokenization and scoring settings of an [`InMemoryModel`], set up through
+/// its `with_*` methods and saved along with the index.
+#[derive(Default, Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Config {
+    lexer: LexerConfig,
+    scorer: Scorer,
+    substring_fallback: bool,
+    calibration: Calibration,
+    term_boosts: HashMap<String, f32>,
+    max_postings_scanned: Option<usize>,
+    position_weighting: PositionWeighting,
+    min_query_term_len: usize,
+    short_query_terms: ShortTermPolicy,
+    max_vocabulary: Option<usize>,
+    field_fusion: Option<FieldFusion>,
+    field_weights: HashMap<String, f32>,
+    score_decimals: Option<u32>,
+    idf_staleness: f32,
+    max_document_terms: Option<usize>,
+    min_query_idf: Option<f32>,
+    max_query_terms: MaxQueryTerms,
+    query_term_overflow: QueryTermOverflow,
+    include_zero_scores: bool,
+    collapse_query_terms: bool,
+    /// Terms merged into others by [`InMemoryModel::merge_terms`], with the
+    /// terms they were merged into.
+    term_aliases: HashMap<String, String>,
+}
+
+impl Config {
+    pub fn lexer(&self) -> LexerConfig {
+        self.lexer
+    }
+}
+
+/// Dimensions of the TF-IDF vectors of the documents of an [`InMemoryModel`],
+/// see [`InMemoryModel::vector_space`].
+pub struct VectorSpace<'a> {
+    model: &'a InMemoryModel,
+    dimensions: HashMap<String, u32>,
+}
+
+impl VectorSpace<'_> {
+    /// Number of dimensions, i.e. of distinct terms.
+    pub fn len(&self) -> usize {
+        self.dimensions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dimensions.is_empty()
+    }
+
+    pub fn dimension(&self, term: &str) -> Option<u32> {
+        self.dimensions.get(term).copied()
+    }
+
+    /// The nonzero `(dimension, weight)` pairs of the TF-IDF vector of the
+    /// document at `path`, by dimension, `None` if it is not indexed.
+    pub fn sparse_vector(&self, path: &Path) -> Option<Vec<(u32, f32)>> {
+        let mut vector = self.model.document_tfidf_vector(path)?.into_iter()
+            .filter(|(_, weight)| *weight != 0.0)
+            .filter_map(|(term, weight)| Some((self.dimension(&term)?, weight)))
+            .collect::<Vec<_>>();
+        vector.sort_by_key(|(dimension, _)| *dimension);
+        Some(vector)
+    }
+}
+
+/// A document tokenized ahead of time by [`InMemoryModel::analyze`], so the
+/// CPU bound part of indexing can run without access to the model.
+pub struct AnalyzedDocument {
+    lexer: LexerConfig,
+    doc: Doc,
+}
+
+/// Version of the JSON format of saved [`InMemoryModel`]s, bumped whenever a
+/// change cannot be read by older versions of the crate.
+///
+/// Everything added to the format has a default, so an index saved by an
+/// older version loads with the defaults for what it lacks and, where those
+/// are derived from the rest (such as the postings), with them rebuilt. Unknown
+/// fields are ignored, so an index saved by a newer version with the same
+/// format version loads too, without what was added. An index with a newer
+/// format version is refused instead of being misread.
+pub const FORMAT_VERSION: u32 = 2;
+
+fn serialize_format_version<S>(_: &u32, serializer: S) -> Result<S::Ok, S::Error> where S: serde::Serializer {
+    serializer.serialize_u32(FORMAT_VERSION)
+}
+
+#[derive(Deserialize)]
+struct FormatHeader {
+    #[serde(default)]
+    format_version: u32,
+}
 
 #[derive(Default, Deserialize, Serialize)]
 pub struct InMemoryModel {
+    /// 0 for indexes saved before the format was versioned. Always saved as
+    /// [`FORMAT_VERSION`].
+    #[serde(default, serialize_with = "serialize_format_version")]
+    format_version: u32,
+    // Documents and postings refer to documents by id rather than by path, so
+    // that long paths are stored once rather than for every document and term.
+    #[serde(default, rename = "documents")]
     docs: Docs,
+    /// Documents of indexes saved before they were keyed by id, moved into
+    /// `docs` on load.
+    #[serde(default, rename = "docs", skip_serializing)]
+    docs_by_path: HashMap<PathBuf, Doc>,
     pub df: DocFreq,
+    #[serde(default, rename = "doc_postings")]
+    postings: Postings,
+    /// Path of every document id, `None` for removed documents until
+    /// [`Model::compact_paths`]. The only copy of the paths, shared with `ids`.
+    #[serde(default)]
+    paths: Vec<Option<Arc<Path>>>,
+    #[serde(skip)]
+    ids: HashMap<Arc<Path>, DocId>,
+    #[serde(default)]
+    config: Config,
+    /// IDF of the indexed terms looked up since the index had
+    /// `idf_document_count` documents, computed for that many, kept when
+    /// [`InMemoryModel::with_idf_staleness`] is set.
+    #[serde(skip)]
+    idf_cache: RwLock<HashMap<String, f32>>,
+    #[serde(skip)]
+    idf_document_count: usize,
+    #[serde(skip)]
+    idf_cache_hits: AtomicUsize,
+    #[serde(skip)]
+    idf_cache_misses: AtomicUsize,
+    #[serde(skip)]
+    debug_results: bool,
 }
 
-impl Model for InMemoryModel {
-    fn search_query(&self, query: &[char]) -> Result<Vec<(PathBuf, f32)>, ()> {
-        let mut result = Vec::<(PathBuf, f32)>::new();
-        let tokens = Lexer::new(&query).collect::<Vec<_>>();
-        for (path, doc) in &self.docs {
-            let mut rank = 0f32;
-            for token in &tokens {
-                rank += compute_tf(token, doc) * compute_idf(&token, self.docs.len(), &self.df);
+impl InMemoryModel {
+    /// Loads an index saved as JSON together with the configuration it was
+    /// built with. If `config` is given, it must match the saved one, since
+    /// querying with different settings silently produces wrong results. See
+    /// [`FORMAT_VERSION`] for which indexes can be loaded.
+    pub fn load(index_path: &Path, config: Option<&Config>) -> Result<Self, ()> {
+        let open = || File::open(index_path).map(BufReader::new).map_err(|err| {
+            eprintln!("ERROR: could not open index file {index_path}: {err}", index_path = index_path.display());
+        });
+        let check_version = |version: u32| {
+            if version > FORMAT_VERSION {
+                eprintln!("ERROR: index file {index_path} has format version {version}, newer than the supported {FORMAT_VERSION}",
+                          index_path = index_path.display());
+                return Err(())
             }
-            result.push((path.clone(), rank));
-        }
-        result.sort_by(|(_, rank1), (_, rank2)| rank1.partial_cmp(rank2).unwrap());
-        result.reverse();
-        Ok(result)
-    }
-
-    fn add_document(&mut self, file_path: PathBuf, content: &[char]) -> Result<(), ()> {
-        let mut tf = TermFreq::new();
+            Ok(())
+        };
 
-        let mut count = 0;
-        for term in Lexer::new(content) {
-            if let Some(freq) = tf.get_mut(&term) {
-                *freq += 1;
-            } else {
-                tf.insert(term, 1);
+        let mut model: Self = match serde_json::from_reader(open()?) {
+            Ok(model) => model,
+            Err(err) => {
+                // A newer format may fail to parse before its version is
+                // seen, so look for the version alone to explain why.
+                if let Ok(header) = serde_json::from_reader::<_, FormatHeader>(open()?) {
+                    check_version(header.format_version)?;
+                }
+                eprintln!("ERROR: could not parse index file {index_path}: {err}", index_path = index_path.display());
+                return Err(())
             }
-            count += 1;
+        };
+        check_version(model.format_version)?;
+
+        if !model.docs_by_path.is_empty() {
+            let docs = model.docs_by_path.drain().map(|(path, doc)| (Arc::from(path), doc)).collect();
+            model.reindex(docs);
+        } else if model.paths.iter().flatten().count() != model.docs.len()
+            || model.docs.keys().any(|id| model.paths.get(*id as usize).is_none_or(Option::is_none)) {
+            eprintln!("ERROR: index file {index_path} refers to documents without a path", index_path = index_path.display());
+            return Err(())
+        } else {
+            model.ids = model.paths.iter().enumerate()
+                .filter_map(|(id, path)| Some((path.clone()?, id as DocId)))
+                .collect();
         }
 
-        for t in tf.keys() {
-            if let Some(freq) = self.df.get_mut(t) {
-                *freq += 1;
-            } else {
-                self.df.insert(t.to_string(), 1);
+        if let Some(config) = config {
+            if *config != model.config {
+                eprintln!("ERROR: index file {index_path} was built with a different configuration: {saved:?}",
+                          index_path = index_path.display(),
+                          saved = model.config);
+                return Err(())
             }
         }
 
-        self.docs.insert(file_path, Doc {count, tf});
-        Ok(())
+        // The IDFs are not saved, so they are computed for the documents loaded.
+        model.refresh_idf();
+        Ok(model)
     }
-}
 
-pub struct SqliteModel {
-    connection: sqlite::Connection,
-}
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
 
-impl SqliteModel {
-    fn execute(&self, statement: &str) -> Result<(), ()> {
-        self.connection.execute(statement).map_err(|err| {
-            eprintln!("ERROR: could not execute query {statement}: {err}")
-        })?;
-        Ok(())
+    fn doc(&self, path: &Path) -> Option<&Doc> {
+        self.docs.get(self.ids.get(path)?)
     }
 
-    pub fn begin(&self) -> Result<(), ()> {
-        self.execute("BEGIN;")
+    fn doc_mut(&mut self, path: &Path) -> Option<&mut Doc> {
+        self.docs.get_mut(self.ids.get(path)?)
     }
 
-    pub fn commit(&self) -> Result<(), ()> {
-        self.execute("COMMIT;")
+    fn path(&self, id: DocId) -> &Path {
+        self.paths[id as usize].as_deref().expect("indexed document has a path")
     }
 
-    fn migrate(&self) -> Result<(), ()>{
-        self.execute("
-             CREATE TABLE IF NOT EXISTS documents (
-                 id INTEGER NOT NULL PRIMARY KEY,
-                 path TEXT NOT NULL UNIQUE,
-                 term_count INTEGER NOT NULL
-             );
-         ")?;
+    /// Every indexed document along with its path.
+    fn documents(&self) -> impl Iterator<Item = (&Path, &Doc)> {
+        self.docs.iter().map(|(id, doc)| (self.path(*id), doc))
+    }
 
-         self.execute("
-             CREATE TABLE IF NOT EXISTS term_freq (
-                 term TEXT NOT NULL,
-                 doc_id INTEGER NOT NULL,
-                 freq INTEGER NOT NULL,
-                 UNIQUE(term, doc_id),
-                 FOREIGN KEY(doc_id) REFERENCES documents(id)
-             );
-        ")?;
+    /// When none of the query tokens is in the vocabulary, match them as
+    /// substrings of the indexed terms instead (e.g. "fig" finds "config").
+    /// Off by default since it scans the whole vocabulary and is less precise.
+    pub fn with_substring_fallback(mut self, enabled: bool) -> Self {
+        self.config.substring_fallback = enabled;
+        self
+    }
 
-         self.execute("
-             CREATE TABLE IF NOT EXISTS doc_freq (
-                 term TEXT NOT NULL UNIQUE,
-                 freq INTEGER
-             );
-         ")?;
+    /// Tokenization used both for indexed documents and for queries, so it has
+    /// to be set before adding any documents.
+    pub fn with_lexer(mut self, lexer: LexerConfig) -> Self {
+        self.config.lexer = lexer;
+        self
+    }
 
-         Ok(())
+    /// Shorthand for [`InMemoryModel::with_lexer`] with the options of a preset.
+    /// Use `with_lexer(LexerConfig { ..preset.into() })` to override some of them.
+    pub fn with_preset(self, preset: Preset) -> Self {
+        self.with_lexer(preset.into())
     }
 
-    pub fn open(path: &Path) -> Result<Self, ()> {
-        let connection = sqlite::open(path).map_err(|err| {
-            eprintln!("ERROR: could not open sqlite database {path}: {err}", path = path.display())
-        })?;
+    fn lexer<'a>(&self, content: &'a [char]) -> Lexer<'a> {
+        Lexer::with_config(content, self.config.lexer)
+    }
 
-        let this = Self {connection};
+    /// The term searched for the query token `token`, which differs from it
+    /// once it was merged into another with [`InMemoryModel::merge_terms`].
+    fn resolve_alias(&self, token: String) -> String {
+        match self.config.term_aliases.get(&token) {
+            Some(term) => term.clone(),
+            None => token,
+        }
+    }
 
-        this.migrate().map_err(|err| {
-            eprintln!("ERROR: error occured during migration {err:?})");
-        })?;
+    fn positioned_query_terms(&self, query: &[char]) -> Vec<(usize, String)> {
+        positioned_terms(self.lexer(query)).into_iter()
+            .map(|(position, token)| (position, self.resolve_alias(token)))
+            .collect()
+    }
 
-        Ok(this)
+    pub fn with_scorer(mut self, scorer: Scorer) -> Self {
+        self.config.scorer = scorer;
+        self
     }
-}
 
+    pub fn with_calibration(mut self, calibration: Calibration) -> Self {
+        self.config.calibration = calibration;
+        self
+    }
 
-impl Model for SqliteModel {
-    fn search_query(&self, query: &[char]) -> Result<Vec<(PathBuf, f32)>, ()> {
-        todo!()
+    /// Multiply the contribution of the given terms to every document's score,
+    /// e.g. to make product names or error codes count for more. Terms without
+    /// a boost keep a multiplier of 1.0. The terms are tokenized with the
+    /// current lexer configuration, so set that first.
+    pub fn with_term_boosts(mut self, boosts: HashMap<String, f32>) -> Self {
+        self.config.term_boosts.clear();
+        for (term, boost) in boosts {
+            for token in self.lexer(&term.chars().collect::<Vec<_>>()) {
+                self.config.term_boosts.insert(token, boost);
+            }
+        }
+        self
     }
 
-    fn add_document(&mut self, file_path: PathBuf, content: &[char]) -> Result<(), ()> {
-        let terms = Lexer::new(content).collect::<Vec<_>>();
+    /// Stop visiting postings once `max` of them have been scanned and return the
+    /// best results found so far. Rarest query terms are visited first, so the
+    /// budget goes to the postings that discriminate the most.
+    pub fn with_max_postings_scanned(mut self, max: Option<usize>) -> Self {
+        self.config.max_postings_scanned = max;
+        self
+    }
 
-        let doc_id = {
-            let query = "INSERT INTO document (path, term_count) VALUES (:path, :count) RETURNING id";
-            let log_err = |err| {
-                eprintln!("ERROR: could not prepare or execute query {query}: {err}")
-            };
-            let mut stmt = self.connection.prepare(query).map_err(log_err)?;
+    pub fn with_position_weighting(mut self, weighting: PositionWeighting) -> Self {
+        self.config.position_weighting = weighting;
+        self
+    }
 
-            stmt.bind_iter::<_, (_, sqlite::Value)>([
-                (":path", file_path.display().to_string().as_str().into()),
-                (":count", (terms.len() as i64).into()),
-            ]).map_err(log_err)?;
+    /// Handle query terms shorter than `min_len` chars according to `policy`,
+    /// since single letters are expensive and rarely useful as exact matches.
+    /// The default minimum of 0 leaves every term as it is.
+    pub fn with_min_query_term_len(mut self, min_len: usize, policy: ShortTermPolicy) -> Self {
+        self.config.min_query_term_len = min_len;
+        self.config.short_query_terms = policy;
+        self
+    }
 
-            stmt.next().map_err(log_err)?;
+    /// Cap the number of distinct terms at `max`. Whenever indexing a document
+    /// exceeds it, the terms found in the fewest documents are dropped from the
+    /// index altogether, trading recall on rare terms for bounded memory.
+    /// Of terms found in as many documents, those of the document just indexed
+    /// are kept, so that a term evicted before gets to be counted again when
+    /// it comes back rather than being evicted on arrival every time.
+    /// Document lengths are left as they were, so the remaining terms score
+    /// the same as before.
+    pub fn with_max_vocabulary(mut self, max: Option<usize>) -> Self {
+        self.config.max_vocabulary = max;
+        self
+    }
 
-            match stmt.next().map_err(log_err)? {
-                sqlite::State::Row => stmt.read::<i64, _>("id").map_err(log_err)?,
-                sqlite::State::Done => 0
-            }
-        };
+    /// Score documents added with [`InMemoryModel::add_document_with_fields`]
+    /// field by field, combining the field scores as `fusion` says. A field
+    /// missing from `weights` has a weight of 1. Searches that score documents
+    /// one at a time, such as [`InMemoryModel::search_boolean`], fall back from
+    /// [`FieldFusion::ReciprocalRank`] to [`FieldFusion::WeightedSum`].
+    pub fn with_field_fusion(mut self, fusion: FieldFusion, weights: HashMap<String, f32>) -> Self {
+        self.config.field_fusion = Some(fusion);
+        self.config.field_weights = weights;
+        self
+    }
 
-        let mut tf = TermFreq::new();
-        for term in Lexer::new(content) {
-            if let Some(freq) = tf.get_mut(&term) {
-                *freq += 1;
-            } else {
-                tf.insert(term, 1);
-            }
+    /// Round scores to `decimals` decimal places, so that documents whose
+    /// scores only differ by floating point noise, which depends on the
+    /// platform and on the order terms are summed in, tie and are ordered by
+    /// path instead. Off by default.
+    pub fn with_score_rounding(mut self, decimals: Option<u32>) -> Self {
+        self.config.score_decimals = decimals;
+        self
+    }
+
+    /// Let IDFs go stale until the number of documents has changed by more
+    /// than `fraction` of what it was when they were last computed, so that
+    /// caches of scores built on top of them need not be invalidated by every
+    /// single document added. IDFs are computed when first needed and then
+    /// cached until the next recomputation, see [`InMemoryModel::idf_cache_stats`].
+    /// The IDF of a term whose document frequency has not changed since is
+    /// off by at most `log10(1 + fraction)`. A term found in more documents
+    /// than were counted gets an IDF of 0 rather than a negative one, which is
+    /// within the same bound. Defaults to 0, i.e. IDFs are always exact and
+    /// never cached.
+    pub fn with_idf_staleness(mut self, fraction: f32) -> Self {
+        self.config.idf_staleness = fraction;
+        self.idf_cache_mut().clear();
+        self.idf_document_count = 0;
+        self.refresh_idf();
+        self
+    }
+
+    /// Number of documents the IDFs were last computed for, which lags behind
+    /// [`Model::document_count`] within [`InMemoryModel::with_idf_staleness`].
+    pub fn idf_document_count(&self) -> usize {
+        if self.config.idf_staleness > 0.0 {
+            self.idf_document_count
+        } else {
+            self.docs.len()
         }
+    }
 
-        for (term, freq) in &tf {
-            {
-                let query = "INSERT INTO term_freq(doc_id, term, freq) VALUES (:doc_id, :term, :freq)";
-                let log_err = |err| {
-                    eprintln!("ERROR: could not execute or prepare query {query}: {err}");
-                };
-                let mut stmt = self.connection.prepare(query).map_err(log_err)?;
-                stmt.bind_iter::<_, (_, sqlite::Value)>([
-                    (":doc_id", doc_id.into()),
-                    (":term", term.as_str().into()),
-                    (":freq", (*freq as i64).into()),
-                ]).map_err(log_err)?;
-                stmt.next().map_err(log_err)?;
-            }
+    fn refresh_idf(&mut self) {
+        if self.config.idf_staleness <= 0.0 {
+            return
+        }
+        let n = self.docs.len();
+        let changed = n.abs_diff(self.idf_document_count) as f32;
+        if changed == 0.0 || changed <= self.config.idf_staleness * self.idf_document_count as f32 {
+            return
+        }
+        self.idf_cache_mut().clear();
+        self.idf_document_count = n;
+    }
 
-            {
-                let freq = {
-                    let query = "SELECT freq FROM doc_freq WHERE term = :term";
-                    let log_err = |err| {
-                        eprintln!("ERROR: could not prepare or execute query {query}: {err}");
-                    };
-                    let mut stmt = self.connection.prepare(query).map_err(log_err)?;
-                    stmt.bind_iter::<_, (_, sqlite::Value)>([
-                        (":term", term.as_str().into()),
-                    ]).map_err(log_err)?;
-                    match stmt.next().map_err(log_err)? {
-                        sqlite::State::Row => stmt.read::<i64, _>("freq").map_err(log_err)?,
-                        sqlite::State::Done => 0
-                    }
-                };
+    fn idf_cache_mut(&mut self) -> &mut HashMap<String, f32> {
+        self.idf_cache.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
 
-                // TODO: find a better way to auto increment the frequency
-                let query = "INSERT OR REPLACE INTO doc_freq(term, freq) VALUES (:term, :freq)";
-                let log_err = |err| {
-                    eprintln!("ERROR: could not execute or prepare query {query}: {err}");
-                };
-                let mut stmt = self.connection.prepare(query).map_err(log_err)?;
-                stmt.bind_iter::<_, (_, sqlite::Value)>([
-                    (":term", term.as_str().into()),
-                    (":freq", (freq + 1).into()),
-                ]).map_err(log_err)?;
-                stmt.next().map_err(log_err)?;
+    fn idf(&self, term: &str) -> f32 {
+        if self.config.idf_staleness <= 0.0 {
+            return compute_idf(term, self.docs.len(), &self.df)
+        }
+        if let Some(idf) = self.idf_cache.read().unwrap_or_else(PoisonError::into_inner).get(term) {
+            self.idf_cache_hits.fetch_add(1, Relaxed);
+            return *idf
+        }
+        self.idf_cache_misses.fetch_add(1, Relaxed);
+        // A term may be in more documents than there were at the last
+        // recomputation, it is then in about all of them.
+        let n = self.idf_document_count.max(self.df.get(term).copied().unwrap_or(0));
+        let idf = compute_idf(term, n, &self.df);
+        // Only indexed terms, so that queries cannot grow the cache at will.
+        if self.df.contains_key(term) {
+            self.idf_cache.write().unwrap_or_else(PoisonError::into_inner).insert(term.to_string(), idf);
+        }
+        idf
+    }
+
+    /// Lookups of IDFs in the cache kept with [`InMemoryModel::with_idf_staleness`]
+    /// since the model was created or loaded, a miss being an IDF computed.
+    pub fn idf_cache_stats(&self) -> CacheStats {
+        CacheStats { hits: self.idf_cache_hits.load(Relaxed), misses: self.idf_cache_misses.load(Relaxed) }
+    }
+
+    fn round_score(&self, score: f32) -> f32 {
+        match self.config.score_decimals {
+            Some(decimals) => {
+                let scale = 10f64.powi(decimals as i32);
+                ((score as f64 * scale).round() / scale) as f32
             }
+            None => score,
         }
-        
-        Ok(())
     }
-}
\ No newline at end of file
+
+    /// Folds the term `from` into the term `into` across the whole index, as
+    /// if every occurrence of `from` had been `into` all along, e.g. to
+    /// treat "js" as "javascript" without reindexing. Both are tokenized with
+    /// the model's lexer and have to come out as a single token. From then on
+    /// `from` is also indexed and searched as `into`, which is saved with the
+    /// configuration.
+    pub fn merge_terms(&mut self, from: &str, into: &str) -> Result<(), ()> {
+        let single_token = |text: &str| -> Result<String, ()> {
+            match self.lexer(&text.chars().collect::<Vec<_>>()).collect::<Vec<_>>().as_slice() {
+                [token] => Ok(token.clone()),
+                tokens => {
+                    eprintln!("ERROR: {text:?} is not a single term but {tokens:?}");
+                    Err(())
+                }
+            }
+        };
+        let (from, into) = (single_token(from)?, self.resolve_alias(single_token(into)?));
+        if let Some(merged) = self.config.term_aliases.get(&from) {
+            eprintln!("ERROR: {from:?} was already merged into {merged:?}");
+            return Err(())
+        }
+        if from == into {
+            return Ok(())
+        }
+        for target in self.config.term_aliases.values_mut() {
+            if *target == from {
+                *target = into.clone();
+            }
+        }
+        self.config.term_aliases.insert(from.clone(), into.clone());
+
+        let Some(ids) = self.postings.remove(&from) else {
+            return Ok(())
+        };
+        self.df.remove(&from);
+        for id in &ids {
+            let Some(doc) = self.docs.get_mut(id) else {
+                continue
+            };
+            if !doc.tf.contains_key(&into) {
+                *self.df.entry(into.clone()).or_default() += 1;
+            }
+            merge_doc_terms(doc, &from, &into);
+        }
+        self.postings.entry(into.clone()).or_default().extend(ids);
+        let cache = self.idf_cache_mut();
+        cache.remove(&from);
+        cache.remove(&into);
+        Ok(())
+    }
+
+    /// Index only the first `max` terms of every document, so that a few huge
+    /// documents cannot take up most of the memory. Whether a document was cut
+    /// short is reported by [`InMemoryModel::is_truncated`]. Off by default.
+    pub fn with_max_document_terms(mut self, max: Option<usize>) -> Self {
+        self.config.max_document_terms = max;
+        self
+    }
+
+    /// Whether the document at `path` was longer than the limit set with
+    /// [`InMemoryModel::with_max_document_terms`], `None` if it is not indexed.
+    pub fn is_truncated(&self, path: &Path) -> Option<bool> {
+        self.doc(path).map(|doc| doc.truncated)
+    }
+
+    /// Ignore the query terms whose IDF is below `min_idf`, i.e. those too
+    /// common in the corpus to tell documents apart, as a stop word list that
+    /// follows the corpus. A query made only of such terms is searched with
+    /// all of them rather than none.
+    pub fn with_min_query_idf(mut self, min_idf: Option<f32>) -> Self {
+        self.config.min_query_idf = min_idf;
+        self
+    }
+
+    /// Also return the documents containing none of the query terms, i.e. the
+    /// whole corpus, from [`Model::search_query`] and the other searches. By
+    /// default only the documents found in the postings of a query term are
+    /// returned, as [`SqliteModel`] does, so a result can still score 0.0 when
+    /// the terms it contains are in every document.
+    pub fn with_zero_scores(mut self, include: bool) -> Self {
+        self.config.include_zero_scores = include;
+        self
+    }
+
+    /// Weigh query terms by the number of times they occur in the query, the
+    /// `qtf` of [`Scorer::TfIdf`], or count repeated ones once when off, for
+    /// queries whose wording repeats terms without meaning to stress them.
+    /// On by default.
+    pub fn with_query_term_frequency(mut self, weigh: bool) -> Self {
+        self.config.collapse_query_terms = !weigh;
+        self
+    }
+
+    /// Fill in [`SearchResult::debug`] in the results of
+    /// [`InMemoryModel::search_results`], to check scores by hand. Off by
+    /// default to spare the allocations, and not saved with the index.
+    pub fn with_debug_results(mut self, debug: bool) -> Self {
+        self.debug_results = debug;
+        self
+    }
+
+    /// Handle queries of more than `max` terms according to `policy`, counted
+    /// after tokenization, so that dropped stop words do not count, and again
+    /// once short terms are expanded by [`ShortTermPolicy::ExpandPrefix`] or
+    /// tokens matched as substrings by [`InMemoryModel::with_substring_fallback`],
+    /// since either can turn a single token into the whole vocabulary. Guards a
+    /// public search against queries made expensive on purpose. Defaults to
+    /// [`DEFAULT_MAX_QUERY_TERMS`] and [`QueryTermOverflow::Truncate`].
+    pub fn with_max_query_terms(mut self, max: usize, policy: QueryTermOverflow) -> Self {
+        self.config.max_query_terms = MaxQueryTerms(max);
+        self.config.query_term_overflow = policy;
+        self
+    }
+
+    /// Enforces [`InMemoryModel::with_max_vocabulary`] after indexing the
+    /// document `added`.
+    fn evict_rare_terms(&mut self, added: DocId) {
+        let Some(max) = self.config.max_vocabulary else {
+            return
+        };
+        if self.df.len() <= max {
+            return
+        }
+        let added = &self.docs[&added];
+        let mut terms = self.df.iter()
+            .map(|(term, freq)| (*freq, added.tf.contains_key(term), term.clone()))
+            .collect::<Vec<_>>();
+        let excess = terms.len() - max;
+        if excess < terms.len() {
+            terms.select_nth_unstable(excess);
+            terms.truncate(excess);
+        }
+        for (_, _, term) in terms {
+            self.df.remove(&term);
+            for id in self.postings.remove(&term).into_iter().flatten() {
+                if let Some(doc) = self.docs.get_mut(&id) {
+                    remove_doc_term(doc, &term);
+                }
+            }
+        }
+    }
+
+    fn substring_terms(&self, tokens: &[String]) -> Vec<(String, f32)> {
+        let mut terms = self.df.keys()
+            .filter(|term| tokens.iter().any(|token| term.contains(token.as_str())))
+            .map(|term| (term.clone(), SUBSTRING_PENALTY))
+            .collect::<Vec<_>>();
+        // Sorted so that the terms kept by with_max_query_terms do not
+        // depend on the order of the hash map.
+        terms.sort_by(|(term1, _), (term2, _)| term1.cmp(term2));
+        terms
+    }
+
+    /// Tokenizes `content` the way [`Model::add_document`] would, without
+    /// touching the index. The terms are cut off and merged as they would be
+    /// when indexed, see [`InMemoryModel::with_max_document_terms`] and
+    /// [`InMemoryModel::merge_terms`].
+    pub fn preview_document(&self, content: &[char]) -> DocumentPreview {
+        let mut preview = DocumentPreview::default();
+        let mut doc = Doc::default();
+        let mut position = 0;
+        let mut lexer = self.lexer(content);
+        while let Some(traced) = lexer.next_traced() {
+            match traced {
+                Traced::Token { token, unstemmed, .. } => {
+                    if let Some(word) = unstemmed {
+                        preview.stemmed.push((word, token.clone()));
+                    }
+                    *doc.tf.entry(token.clone()).or_default() += 1;
+                    doc.positions.entry(token).or_default().push(position);
+                    doc.count += 1;
+                    position += 1;
+                }
+                Traced::Dropped { text, reason, .. } => {
+                    // Stop words take up a position, as in positioned_terms.
+                    if reason == DropReason::StopWord {
+                        position += 1;
+                    }
+                    preview.dropped.push((text, reason));
+                }
+            }
+        }
+        self.prepare_doc(&mut doc);
+        preview.tf = doc.tf;
+        preview.count = doc.count;
+        preview
+    }
+
+    /// Applies what the configuration does to a document on top of
+    /// tokenization, before it is indexed.
+    fn prepare_doc(&self, doc: &mut Doc) {
+        if let Some(max) = self.config.max_document_terms {
+            doc.truncated |= truncate_doc(doc, max);
+        }
+        for (from, into) in &self.config.term_aliases {
+            if doc.tf.contains_key(from) {
+                merge_doc_terms(doc, from, into);
+            }
+        }
+    }
+
+    fn tfidf_vector(&self, doc: &Doc) -> HashMap<String, f32> {
+        doc.tf.keys()
+            .map(|term| (term.clone(), compute_tf(term, doc) * self.idf(term)))
+            .collect()
+    }
+
+    /// The TF-IDF weight of every term of the document at `path`.
+    pub fn document_tfidf_vector(&self, path: &Path) -> Option<HashMap<String, f32>> {
+        self.doc(path).map(|doc| self.tfidf_vector(doc))
+    }
+
+    /// Numbers every term of the vocabulary, in alphabetical order, as a
+    /// dimension of the TF-IDF vectors of the documents, for exporting them to
+    /// a vector database. The model cannot change while the space exists, so
+    /// all the vectors exported through it share the same dimensions.
+    pub fn vector_space(&self) -> VectorSpace<'_> {
+        let mut terms = self.df.keys().collect::<Vec<_>>();
+        terms.sort();
+        let dimensions = terms.into_iter().enumerate().map(|(dimension, term)| (term.clone(), dimension as u32)).collect();
+        VectorSpace { model: self, dimensions }
+    }
+
+    /// Cosine similarity of the TF-IDF vectors of two indexed documents.
+    pub fn similarity(&self, a: &Path, b: &Path) -> Option<f32> {
+        Some(cosine_similarity(&self.document_tfidf_vector(a)?, &self.document_tfidf_vector(b)?))
+    }
+
+    /// The `k` documents most similar to the one at `path`. Only documents
+    /// sharing at least one term with it are compared.
+    pub fn nearest_documents(&self, path: &Path, k: usize) -> Result<Ranking, ()> {
+        let id = self.ids.get(path).copied().ok_or_else(|| {
+            eprintln!("ERROR: document {path} is not indexed", path = path.display());
+        })?;
+        let doc = &self.docs[&id];
+        let vector = self.tfidf_vector(doc);
+
+        let candidates = doc.tf.keys()
+            .flat_map(|term| self.postings.get(term).into_iter().flatten())
+            .filter(|candidate| **candidate != id)
+            .collect::<BTreeSet<_>>();
+
+        let mut top = TopK::new(k);
+        for candidate in candidates {
+            let similarity = cosine_similarity(&vector, &self.tfidf_vector(&self.docs[candidate]));
+            top.push(self.path(*candidate).to_path_buf(), similarity);
+        }
+        Ok(top.into_ranking())
+    }
+
+    /// The `k` documents most relevant to the topic described by `queries`,
+    /// with their scores for the individual queries combined by `combine`.
+    /// Each query is tokenized once, however many documents there are.
+    pub fn score_topic(&self, queries: &[Vec<char>], combine: TopicCombine, k: usize) -> Result<Ranking, ()> {
+        let queries = queries.iter().map(|query| self.query_terms(query)).collect::<Result<Vec<_>, _>>()?;
+        let mut top = TopK::new(k);
+        for (path, doc) in self.documents() {
+            if !self.config.include_zero_scores && !queries.iter().any(|terms| contains_any(terms, doc)) {
+                continue
+            }
+            let scores = queries.iter().map(|terms| self.score(terms, doc));
+            let score = match combine {
+                TopicCombine::Max => scores.fold(0.0, f32::max),
+                TopicCombine::Mean => scores.sum::<f32>() / queries.len().max(1) as f32,
+            };
+            top.push(path.to_path_buf(), score);
+        }
+        Ok(top.into_ranking())
+    }
+
+    /// Groups of documents that are near duplicates of each other: two
+    /// documents whose TF-IDF vectors have a cosine similarity above
+    /// `threshold` are in the same group, and so transitively are the
+    /// documents similar to either. Only documents sharing a term are
+    /// compared. Groups are sorted by path, and so is each group.
+    pub fn find_near_duplicates(&self, threshold: f32) -> Vec<Vec<PathBuf>> {
+        // Union-find over document ids.
+        fn root(parents: &mut [usize], mut id: usize) -> usize {
+            while parents[id] != id {
+                parents[id] = parents[parents[id]];
+                id = parents[id];
+            }
+            id
+        }
+        let mut parents = (0..self.paths.len()).collect::<Vec<_>>();
+        let vectors = self.docs.iter().map(|(id, doc)| (*id, self.tfidf_vector(doc))).collect::<HashMap<_, _>>();
+
+        for (id, doc) in &self.docs {
+            let candidates = doc.tf.keys()
+                .flat_map(|term| self.postings.get(term).into_iter().flatten())
+                .filter(|candidate| *candidate > id)
+                .collect::<BTreeSet<_>>();
+            for candidate in candidates {
+                let (Some(a), Some(b)) = (vectors.get(id), vectors.get(candidate)) else {
+                    continue
+                };
+                if cosine_similarity(a, b) > threshold {
+                    let (a, b) = (root(&mut parents, *id as usize), root(&mut parents, *candidate as usize));
+                    parents[a.max(b)] = a.min(b);
+                }
+            }
+        }
+
+        let mut groups = BTreeMap::<usize, Vec<PathBuf>>::new();
+        for id in self.docs.keys() {
+            let group = root(&mut parents, *id as usize);
+            groups.entry(group).or_default().push(self.path(*id).to_path_buf());
+        }
+        let mut groups = groups.into_values().filter(|group| group.len() > 1).collect::<Vec<_>>();
+        for group in &mut groups {
+            group.sort();
+        }
+        groups.sort();
+        groups
+    }
+
+    /// Number of documents where the terms of `phrase` appear consecutively.
+    pub fn phrase_document_frequency(&self, phrase: &[char]) -> usize {
+        let terms = self.positioned_query_terms(phrase);
+        self.docs.values().filter(|doc| contains_phrase(&terms, doc)).count()
+    }
+
+    /// Keeps the first occurrence of every term only, unless repetitions are
+    /// to be weighed, see [`InMemoryModel::with_query_term_frequency`].
+    fn collapse_query_terms(&self, tokens: &mut Vec<String>) {
+        if self.config.collapse_query_terms {
+            let mut seen = HashSet::new();
+            tokens.retain(|token| seen.insert(token.clone()));
+        }
+    }
+
+    /// Applies [`InMemoryModel::with_max_query_terms`] to the terms of a query.
+    fn limit_query_terms<T>(&self, terms: &mut Vec<T>) -> Result<(), ()> {
+        let MaxQueryTerms(max) = self.config.max_query_terms;
+        if terms.len() > max {
+            match self.config.query_term_overflow {
+                QueryTermOverflow::Truncate => terms.truncate(max),
+                QueryTermOverflow::Error => {
+                    eprintln!("ERROR: query has {count} terms, more than the maximum of {max}", count = terms.len());
+                    return Err(())
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn query_terms(&self, query: &[char]) -> Result<Vec<(String, f32)>, ()> {
+        let mut tokens = self.lexer(query).map(|token| self.resolve_alias(token)).collect::<Vec<_>>();
+        self.collapse_query_terms(&mut tokens);
+        self.limit_query_terms(&mut tokens)?;
+        if self.config.substring_fallback && !tokens.iter().any(|token| self.df.contains_key(token)) {
+            let mut terms = self.substring_terms(&tokens);
+            self.limit_query_terms(&mut terms)?;
+            return Ok(terms)
+        }
+
+        let mut terms = Vec::new();
+        for token in tokens {
+            if token.chars().count() >= self.config.min_query_term_len {
+                terms.push((token, 1.0));
+                continue
+            }
+            match self.config.short_query_terms {
+                ShortTermPolicy::Drop => {}
+                ShortTermPolicy::ExpandPrefix => {
+                    let mut expanded = self.df.keys().filter(|term| term.starts_with(token.as_str())).collect::<Vec<_>>();
+                    expanded.sort();
+                    terms.extend(expanded.into_iter().map(|term| (term.clone(), 1.0)));
+                }
+            }
+        }
+        // A short term may have been expanded into many.
+        self.limit_query_terms(&mut terms)?;
+
+        if let Some(min_idf) = self.config.min_query_idf {
+            if terms.iter().any(|(term, _)| self.idf(term) >= min_idf) {
+                terms.retain(|(term, _)| self.idf(term) >= min_idf);
+            }
+        }
+        Ok(terms)
+    }
+
+    fn confidence(&self, terms: &[(String, f32)], score: f32) -> f32 {
+        let confidence = match self.config.calibration {
+            Calibration::MaxScore if matches!(self.config.scorer, Scorer::Jaccard) => score,
+            Calibration::MaxScore => {
+                let max_idf = (self.docs.len() as f32).log10();
+                let max_score = terms.iter().map(|(_, weight)| weight * max_idf).sum::<f32>() / terms.len() as f32;
+                if max_score > 0.0 { score / max_score } else { 0.0 }
+            }
+            Calibration::Sigmoid { midpoint, steepness } => {
+                1.0 / (1.0 + (-steepness * (score - midpoint)).exp())
+            }
+        };
+        confidence.clamp(0.0, 1.0)
+    }
+
+    /// Like [`Model::search_query`], but every result also carries a confidence
+    /// in [0, 1] computed according to the configured [`Calibration`].
+    pub fn search_with_confidence(&self, query: &[char]) -> Result<Vec<(PathBuf, f32, f32)>, ()> {
+        let terms = self.query_terms(query)?;
+        let mut result = self.rank(&terms);
+        self.drop_unmatched(&mut result, |doc| contains_any(&terms, doc));
+        Ok(result.into_iter().map(|(path, score)| {
+            let confidence = self.confidence(&terms, score);
+            (path, score, confidence)
+        }).collect())
+    }
+
+    /// Top `k` results for `query` leaving out the `exclude`d documents, e.g.
+    /// the ones already shown on previous pages. IDF still accounts for them.
+    pub fn search_excluding(&self, query: &[char], exclude: &HashSet<PathBuf>, k: usize) -> Result<Vec<(PathBuf, f32)>, ()> {
+        let terms = self.query_terms(query)?;
+        let mut result = self.rank_filtered(&terms, |path| !exclude.contains(path));
+        self.drop_unmatched(&mut result, |doc| contains_any(&terms, doc));
+        result.truncate(k);
+        Ok(result)
+    }
+
+    /// Restricts the document at `path` to the users of `groups` in
+    /// [`InMemoryModel::search_query_acl`], or lifts the restriction if there
+    /// are none. The groups stay when the content of the document is indexed
+    /// again.
+    pub fn set_document_groups<I>(&mut self, path: &Path, groups: I) -> Result<(), ()> where I: IntoIterator<Item = GroupId> {
+        let doc = self.doc_mut(path).ok_or_else(|| {
+            eprintln!("ERROR: document {path} is not indexed", path = path.display());
+        })?;
+        doc.groups = groups.into_iter().collect();
+        Ok(())
+    }
+
+    /// Top `k` results for `query` among the documents visible to a user of
+    /// `allowed_groups`: those of any of the groups and those restricted to
+    /// none. Others are left out while ranking, so there are `k` results
+    /// whenever enough visible documents match. IDF still accounts for all
+    /// documents, so scores compare across users.
+    pub fn search_query_acl(&self, query: &[char], allowed_groups: &HashSet<GroupId>, k: usize) -> Result<Ranking, ()> {
+        let terms = self.query_terms(query)?;
+        let mut result = self.rank_filtered(&terms, |path| {
+            let groups = &self.docs[&self.ids[path]].groups;
+            groups.is_empty() || groups.iter().any(|group| allowed_groups.contains(group))
+        });
+        self.drop_unmatched(&mut result, |doc| contains_any(&terms, doc));
+        result.truncate(k);
+        Ok(result)
+    }
+
+    /// Same as [`Model::search_query`], with the length of every document and
+    /// how many of the query terms it contains.
+    pub fn search_results(&self, query: &[char]) -> Result<Vec<SearchResult>, ()> {
+        let terms = self.query_terms(query)?;
+        let distinct = terms.iter().map(|(term, _)| term).collect::<HashSet<_>>();
+        let ranking = self.search_query(query)?;
+        Ok(ranking.into_iter().map(|(path, score)| {
+            let doc = &self.docs[&self.ids[path.as_path()]];
+            let matched_terms = distinct.iter().filter(|term| doc.tf.contains_key(term.as_str())).count();
+            let debug = self.debug_results.then(|| ResultDebug {
+                term_frequencies: distinct.iter().filter_map(|term| Some((term.to_string(), *doc.tf.get(term.as_str())?))).collect(),
+                term_count: doc.count,
+            });
+            SearchResult { term_count: doc.count, matched_terms, path, score, debug }
+        }).collect())
+    }
+
+    /// Breaks the score of the document at `path` for `query` down into the
+    /// contributions of the query terms. Only TF-IDF scoring of whole documents
+    /// can be broken down like that.
+    pub fn explain(&self, query: &[char], path: &Path) -> Result<Explanation, ()> {
+        if self.config.scorer != Scorer::TfIdf || self.config.field_fusion.is_some() {
+            eprintln!("ERROR: only plain TF-IDF scores can be explained");
+            return Err(())
+        }
+        let doc = self.doc(path).ok_or_else(|| {
+            eprintln!("ERROR: document {path} is not indexed", path = path.display());
+        })?;
+
+        let mut terms = Vec::<TermContribution>::new();
+        for (term, weight) in self.query_terms(query)? {
+            if let Some(existing) = terms.iter_mut().find(|existing| existing.term == term) {
+                existing.weight += weight;
+                continue
+            }
+            terms.push(TermContribution {
+                boost: self.config.term_boosts.get(&term).cloned().unwrap_or(1.0),
+                tf: compute_weighted_tf(&term, doc, self.config.position_weighting),
+                idf: self.idf(&term),
+                contribution: 0.0,
+                weight,
+                term,
+            });
+        }
+        for term in &mut terms {
+            term.contribution = term.weight * term.boost * term.tf * term.idf;
+        }
+
+        let score = self.round_score(terms.iter().map(|term| term.contribution).sum());
+        Ok(Explanation { path: path.to_path_buf(), score, terms })
+    }
+
+    /// [`InMemoryModel::explain`] serialized as JSON, for logs and API responses.
+    pub fn explain_json(&self, query: &[char], path: &Path) -> Result<String, ()> {
+        serde_json::to_string(&self.explain(query, path)?).map_err(|err| {
+            eprintln!("ERROR: could not serialize explanation: {err}");
+        })
+    }
+
+    /// Everything the index holds about the document at `path` as JSON: its
+    /// terms with their frequencies and positions, length, fields, access
+    /// groups and version, along with the norm of its TF-IDF vector and the
+    /// boosts of its terms. `None` if it is not indexed.
+    pub fn document_debug_json(&self, path: &Path) -> Option<String> {
+        let id = *self.ids.get(path)?;
+        let doc = &self.docs[&id];
+        fn term_frequencies(doc: &Doc) -> BTreeMap<&str, usize> {
+            doc.tf.iter().map(|(term, freq)| (term.as_str(), *freq)).collect()
+        }
+        let dump = DocumentDump {
+            path,
+            id,
+            term_count: doc.count,
+            truncated: doc.truncated,
+            version: doc.version,
+            groups: &doc.groups,
+            norm: self.tfidf_vector(doc).values().map(|weight| weight * weight).sum::<f32>().sqrt(),
+            term_frequencies: term_frequencies(doc),
+            positions: doc.positions.iter().map(|(term, positions)| (term.as_str(), positions.as_slice())).collect(),
+            term_boosts: doc.tf.keys()
+                .filter_map(|term| Some((term.as_str(), *self.config.term_boosts.get(term)?)))
+                .collect(),
+            fields: doc.fields.iter().map(|(name, field)| {
+                (name.as_str(), FieldDump { term_count: field.count, term_frequencies: term_frequencies(field) })
+            }).collect(),
+        };
+        serde_json::to_string(&dump).map_err(|err| {
+            eprintln!("ERROR: could not serialize document {path}: {err}", path = path.display());
+        }).ok()
+    }
+
+    /// Splits the results into documents matching at least `min_match` distinct
+    /// query terms and near misses matching some but fewer than that, each ranked.
+    pub fn search_with_near_misses(&self, query: &[char], min_match: usize) -> Result<(Ranking, Ranking), ()> {
+        let terms = self.query_terms(query)?;
+        let distinct = terms.iter().map(|(term, _)| term).collect::<HashSet<_>>();
+        let mut matches = Vec::new();
+        let mut near_misses = Vec::new();
+        for (path, rank) in self.rank(&terms) {
+            let doc = &self.docs[&self.ids[path.as_path()]];
+            let matched = distinct.iter().filter(|term| doc.tf.contains_key(term.as_str())).count();
+            if matched >= min_match {
+                matches.push((path, rank));
+            } else if matched > 0 {
+                near_misses.push((path, rank));
+            }
+        }
+        Ok((matches, near_misses))
+    }
+
+    /// Like [`Model::search_query`], but within the budget set by
+    /// [`InMemoryModel::with_max_postings_scanned`]. Only documents reached
+    /// within it are ranked, and the flag tells whether it ran out before all
+    /// postings were visited, making the results approximate.
+    pub fn search_bounded(&self, query: &[char]) -> Result<(Ranking, bool), ()> {
+        let terms = self.query_terms(query)?;
+        let (mut result, approximate) = self.search_bounded_terms(&terms)?;
+        self.drop_unmatched(&mut result, |doc| contains_any(&terms, doc));
+        Ok((result, approximate))
+    }
+
+    fn search_bounded_terms(&self, terms: &[(String, f32)]) -> Result<(Ranking, bool), ()> {
+        let Some(budget) = self.config.max_postings_scanned else {
+            return Ok((self.rank(terms), false))
+        };
+
+        let mut seen = HashSet::new();
+        let mut by_rarity = terms.iter().map(|(term, _)| term).filter(|term| seen.insert(*term)).collect::<Vec<_>>();
+        by_rarity.sort_by_key(|term| self.df.get(*term).cloned().unwrap_or(0));
+
+        let mut candidates = BTreeSet::new();
+        let mut scanned = 0;
+        let mut approximate = false;
+        'scan: for term in by_rarity {
+            for id in self.postings.get(term).into_iter().flatten() {
+                if scanned == budget {
+                    approximate = true;
+                    break 'scan
+                }
+                scanned += 1;
+                candidates.insert(*id);
+            }
+        }
+
+        let mut result = candidates.into_iter()
+            .map(|id| (self.path(id).to_path_buf(), self.score(terms, &self.docs[&id])))
+            .collect();
+        sort_ranking(&mut result);
+        Ok((result, approximate))
+    }
+
+    fn query_matches(&self, query: &Query, doc: &Doc) -> bool {
+        match query {
+            Query::Term(term) => {
+                let terms = self.positioned_query_terms(&term.chars().collect::<Vec<_>>());
+                match terms.as_slice() {
+                    [] => false,
+                    [(_, term)] => doc.tf.contains_key(term),
+                    _ => contains_phrase(&terms, doc),
+                }
+            }
+            Query::Phrase(words) => {
+                let terms = self.positioned_query_terms(&words.join(" ").chars().collect::<Vec<_>>());
+                contains_phrase(&terms, doc)
+            }
+            Query::Field(field, query) => doc.fields.get(field).is_some_and(|field| self.query_matches(query, field)),
+            Query::And(lhs, rhs) => self.query_matches(lhs, doc) && self.query_matches(rhs, doc),
+            Query::Or(lhs, rhs) => self.query_matches(lhs, doc) || self.query_matches(rhs, doc),
+            Query::Not(query) => !self.query_matches(query, doc),
+            Query::Boost(query, _) => self.query_matches(query, doc),
+        }
+    }
+
+    // Terms that contribute to the score of matching documents: everything
+    // outside of a NOT, weighted by the boosts around it.
+    fn scored_query_terms(&self, query: &Query, weight: f32, terms: &mut Vec<(String, f32)>) {
+        match query {
+            Query::Term(term) => {
+                terms.extend(self.lexer(&term.chars().collect::<Vec<_>>()).map(|term| (self.resolve_alias(term), weight)));
+            }
+            Query::Phrase(words) => {
+                terms.extend(self.lexer(&words.join(" ").chars().collect::<Vec<_>>()).map(|term| (self.resolve_alias(term), weight)));
+            }
+            Query::And(lhs, rhs) | Query::Or(lhs, rhs) => {
+                self.scored_query_terms(lhs, weight, terms);
+                self.scored_query_terms(rhs, weight, terms);
+            }
+            Query::Field(_, query) => self.scored_query_terms(query, weight, terms),
+            Query::Not(_) => {}
+            Query::Boost(query, boost) => self.scored_query_terms(query, weight * boost, terms),
+        }
+    }
+
+    // Number of tokens in all the terms and phrases of `query`, negated or not.
+    fn query_term_count(&self, query: &Query) -> usize {
+        match query {
+            Query::Term(term) => self.lexer(&term.chars().collect::<Vec<_>>()).count(),
+            Query::Phrase(words) => self.lexer(&words.join(" ").chars().collect::<Vec<_>>()).count(),
+            Query::And(lhs, rhs) | Query::Or(lhs, rhs) => self.query_term_count(lhs) + self.query_term_count(rhs),
+            Query::Field(_, query) | Query::Not(query) | Query::Boost(query, _) => self.query_term_count(query),
+        }
+    }
+
+    /// Ranks the documents matching a boolean `query` by the TF-IDF of its
+    /// terms that are not negated, in the document as a whole even if they
+    /// only had to match within a field. Dropping terms would change what a
+    /// boolean query matches, so one of more terms than
+    /// [`InMemoryModel::with_max_query_terms`] allows is refused whatever the
+    /// policy.
+    pub fn search_boolean(&self, query: &Query) -> Result<Ranking, ()> {
+        let MaxQueryTerms(max) = self.config.max_query_terms;
+        let count = self.query_term_count(query);
+        if count > max {
+            eprintln!("ERROR: query has {count} terms, more than the maximum of {max}");
+            return Err(())
+        }
+        let mut terms = Vec::new();
+        self.scored_query_terms(query, 1.0, &mut terms);
+        let mut result = self.documents()
+            .filter(|(_, doc)| self.query_matches(query, doc))
+            .map(|(path, doc)| (path.to_path_buf(), self.score(&terms, doc)))
+            .collect();
+        sort_ranking(&mut result);
+        Ok(result)
+    }
+
+    fn score(&self, terms: &[(String, f32)], doc: &Doc) -> f32 {
+        self.round_score(self.raw_score(terms, doc))
+    }
+
+    fn raw_score(&self, terms: &[(String, f32)], doc: &Doc) -> f32 {
+        match (self.config.scorer, self.config.field_fusion) {
+            (Scorer::TfIdf, Some(fusion)) => {
+                let scores = self.field_scores(terms, doc).into_iter().map(|(_, weight, score)| weight * score);
+                match fusion {
+                    FieldFusion::Max => scores.fold(0.0, f32::max),
+                    FieldFusion::WeightedSum | FieldFusion::ReciprocalRank => scores.sum(),
+                }
+            }
+            (Scorer::TfIdf, _) => self.tfidf(terms, doc),
+            (Scorer::Jaccard, _) => compute_jaccard(terms, doc),
+        }
+    }
+
+    fn tfidf(&self, terms: &[(String, f32)], doc: &Doc) -> f32 {
+        let mut rank = 0f32;
+        for (term, weight) in terms {
+            let boost = self.config.term_boosts.get(term).cloned().unwrap_or(1.0);
+            let tf = compute_weighted_tf(term, doc, self.config.position_weighting);
+            rank += weight * boost * tf * self.idf(term);
+        }
+        rank
+    }
+
+    /// Weight and TF-IDF of every field of `doc`, with the document frequencies
+    /// of the whole corpus. A document without fields is a single unnamed one.
+    fn field_scores<'a>(&self, terms: &[(String, f32)], doc: &'a Doc) -> Vec<(&'a str, f32, f32)> {
+        if doc.fields.is_empty() {
+            return vec![("", 1.0, self.tfidf(terms, doc))]
+        }
+        doc.fields.iter().map(|(name, field)| {
+            let weight = self.config.field_weights.get(name).cloned().unwrap_or(1.0);
+            (name.as_str(), weight, self.tfidf(terms, field))
+        }).collect()
+    }
+
+    fn rank_fused<F>(&self, terms: &[(String, f32)], filter: F) -> Vec<(PathBuf, f32)> where F: Fn(&Path) -> bool {
+        let mut by_field = HashMap::<&str, (f32, Vec<(&Path, f32)>)>::new();
+        let mut result = HashMap::<&Path, f32>::new();
+        for (path, doc) in self.documents() {
+            if !filter(path) {
+                continue
+            }
+            result.insert(path, 0.0);
+            for (name, weight, score) in self.field_scores(terms, doc) {
+                if score > 0.0 {
+                    by_field.entry(name).or_insert((weight, Vec::new())).1.push((path, score));
+                }
+            }
+        }
+        for (weight, mut ranking) in by_field.into_values() {
+            ranking.sort_by(|(path1, rank1), (path2, rank2)| rank2.total_cmp(rank1).then_with(|| path1.cmp(path2)));
+            for (rank, (path, _)) in ranking.into_iter().enumerate() {
+                *result.entry(path).or_default() += weight / (RRF_K + rank as f32 + 1.0);
+            }
+        }
+        let mut result = result.into_iter().map(|(path, rank)| (path.to_path_buf(), self.round_score(rank))).collect();
+        sort_ranking(&mut result);
+        result
+    }
+
+    /// Tokenizes `content` with `lexer`, which has to be the lexer of the
+    /// model the document is going to be added to with [`InMemoryModel::add_analyzed`].
+    pub fn analyze(lexer: LexerConfig, content: &[char]) -> AnalyzedDocument {
+        let mut positions = TermPositions::new();
+        let mut terms = Vec::new();
+        for (position, term) in positioned_terms(Lexer::with_config(content, lexer)) {
+            positions.entry(term.clone()).or_default().push(position);
+            terms.push(term);
+        }
+        let (tf, count) = count_terms(terms);
+        AnalyzedDocument { lexer, doc: Doc {count, tf, positions, ..Doc::default()} }
+    }
+
+    /// Same as [`Model::add_document`] with the tokenization already done.
+    pub fn add_analyzed(&mut self, file_path: PathBuf, document: AnalyzedDocument) -> Result<(), ()> {
+        if document.lexer != self.config.lexer {
+            eprintln!("ERROR: document {file_path} was analyzed with a different lexer configuration: {lexer:?}",
+                      file_path = file_path.display(),
+                      lexer = document.lexer);
+            return Err(())
+        }
+        let mut doc = document.doc;
+        self.prepare_doc(&mut doc);
+        // An updated document keeps its id, so updates do not use up ids.
+        let id = self.ids.get(file_path.as_path()).copied();
+        if let Some(old) = id.and_then(|id| self.docs.get(&id)) {
+            doc.groups = old.groups.clone();
+        }
+        self.remove_document(&file_path)?;
+        let id = self.insert_doc(file_path, doc, id)?;
+        self.evict_rare_terms(id);
+        self.refresh_idf();
+        Ok(())
+    }
+
+    /// Indexes `content` as version `version` of the document at `file_path`
+    /// unless a version at least as recent is already indexed, so that updates
+    /// arriving out of order cannot overwrite a newer one with an older one.
+    /// Returns whether the update was applied. Indexing the document through
+    /// anything else forgets its version, and so does removing it.
+    pub fn update_document_versioned(&mut self, file_path: PathBuf, content: &[char], version: u64) -> Result<bool, ()> {
+        if self.document_version(&file_path).is_some_and(|indexed| indexed >= version) {
+            return Ok(false)
+        }
+        self.add_document(file_path.clone(), content)?;
+        if let Some(doc) = self.doc_mut(&file_path) {
+            doc.version = Some(version);
+        }
+        Ok(true)
+    }
+
+    /// Version of the document at `path` as indexed by
+    /// [`InMemoryModel::update_document_versioned`].
+    pub fn document_version(&self, path: &Path) -> Option<u64> {
+        self.doc(path)?.version
+    }
+
+    /// Indexes the raw contents of a file or request body. Invalid UTF-8 is
+    /// replaced with U+FFFD rather than rejected, so the valid text around it
+    /// still gets indexed, and a leading byte order mark is dropped.
+    pub fn add_document_bytes(&mut self, file_path: PathBuf, bytes: &[u8]) -> Result<(), ()> {
+        let content = String::from_utf8_lossy(bytes);
+        let content = content.trim_start_matches(BOM).chars().collect::<Vec<_>>();
+        self.add_document(file_path, &content)
+    }
+
+    /// Indexes a document made of named fields, such as a title and a body.
+    /// The document as a whole is their concatenation in the given order, and
+    /// each field is also kept on its own for [`InMemoryModel::with_field_fusion`].
+    pub fn add_document_with_fields(&mut self, file_path: PathBuf, fields: &[(&str, &[char])]) -> Result<(), ()> {
+        let mut whole = Doc::default();
+        let mut offset = 0;
+        for (name, content) in fields {
+            let mut field = Self::analyze(self.config.lexer, content).doc;
+            if let Some(max) = self.config.max_document_terms {
+                whole.truncated |= truncate_doc(&mut field, max - whole.count);
+                field.truncated = false;
+            }
+            for (term, freq) in &field.tf {
+                *whole.tf.entry(term.clone()).or_default() += freq;
+            }
+            let mut end = 0;
+            for (term, positions) in &field.positions {
+                whole.positions.entry(term.clone()).or_default().extend(positions.iter().map(|position| position + offset));
+                end = end.max(positions.last().map_or(0, |position| position + 1));
+            }
+            offset += end;
+            whole.count += field.count;
+            whole.fields.insert(name.to_string(), field);
+        }
+        for positions in whole.positions.values_mut() {
+            positions.sort();
+        }
+        self.add_analyzed(file_path, AnalyzedDocument { lexer: self.config.lexer, doc: whole })
+    }
+
+    /// Indexes the values at the JSON pointers `field_paths` of the JSON
+    /// document `json` as fields named after the pointers without their
+    /// leading slash, e.g. `/user/name` becomes the field `user/name`. Objects
+    /// and arrays are flattened into the text of all the values within them,
+    /// everything outside of `field_paths` is ignored, and so are pointers
+    /// that match nothing.
+    pub fn add_json_document(&mut self, file_path: PathBuf, json: &str, field_paths: &[&str]) -> Result<(), ()> {
+        let value: serde_json::Value = serde_json::from_str(json).map_err(|err| {
+            eprintln!("ERROR: could not parse JSON document {file_path}: {err}", file_path = file_path.display());
+        })?;
+        let mut fields = Vec::new();
+        for field_path in field_paths {
+            if let Some(value) = value.pointer(field_path) {
+                let mut text = String::new();
+                flatten_json(value, &mut text);
+                fields.push((field_path.trim_start_matches('/'), text.chars().collect::<Vec<_>>()));
+            }
+        }
+        let fields = fields.iter().map(|(name, text)| (*name, text.as_slice())).collect::<Vec<_>>();
+        self.add_document_with_fields(file_path, &fields)
+    }
+
+    /// Documents ranked by how well their field `field` alone matches `query`.
+    pub fn search_field(&self, query: &[char], field: &str) -> Result<Ranking, ()> {
+        let terms = self.query_terms(query)?;
+        let mut result = self.documents().map(|(path, doc)| {
+            let score = doc.fields.get(field).map_or(0.0, |field| self.tfidf(&terms, field));
+            (path.to_path_buf(), self.round_score(score))
+        }).collect();
+        sort_ranking(&mut result);
+        self.drop_unmatched(&mut result, |doc| doc.fields.get(field).is_some_and(|field| contains_any(&terms, field)));
+        Ok(result)
+    }
+
+    /// Documents ranked by how well the fields named `fields` match `query`,
+    /// summing the scores of the fields weighted as set with
+    /// [`InMemoryModel::with_field_fusion`]. No `fields` means all of them,
+    /// which also takes in the documents indexed without fields.
+    pub fn search_in_fields(&self, query: &[char], fields: &[&str]) -> Result<Ranking, ()> {
+        let terms = self.query_terms(query)?;
+        let mut result = self.documents().map(|(path, doc)| {
+            let score = self.field_scores(&terms, doc).into_iter()
+                .filter(|(name, _, _)| fields.is_empty() || fields.contains(name))
+                .map(|(_, weight, score)| weight * score)
+                .sum();
+            (path.to_path_buf(), self.round_score(score))
+        }).collect();
+        sort_ranking(&mut result);
+        self.drop_unmatched(&mut result, |doc| {
+            if fields.is_empty() {
+                contains_any(&terms, doc)
+            } else {
+                fields.iter().any(|name| doc.fields.get(*name).is_some_and(|field| contains_any(&terms, field)))
+            }
+        });
+        Ok(result)
+    }
+
+    /// Indexes `doc` under the id `id` if it is given, which must be free,
+    /// and under a new one otherwise, returning the id.
+    fn insert_doc(&mut self, file_path: PathBuf, doc: Doc, id: Option<DocId>) -> Result<DocId, ()> {
+        let path = Arc::<Path>::from(file_path);
+        let id = match id {
+            Some(id) => id,
+            None => {
+                let id = DocId::try_from(self.paths.len()).map_err(|_| {
+                    eprintln!("ERROR: cannot index {path}, all document ids are taken", path = path.display());
+                })?;
+                self.paths.push(None);
+                id
+            }
+        };
+        self.paths[id as usize] = Some(path.clone());
+        self.ids.insert(path, id);
+        for t in doc.tf.keys() {
+            if let Some(freq) = self.df.get_mut(t) {
+                *freq += 1;
+            } else {
+                self.df.insert(t.to_string(), 1);
+            }
+            self.postings.entry(t.to_string()).or_default().insert(id);
+        }
+        self.docs.insert(id, doc);
+        Ok(id)
+    }
+
+    /// Indexes `docs` anew with ids assigned in the order of their paths,
+    /// deriving the postings from their term frequencies again. The document
+    /// frequencies stay as they are.
+    fn reindex(&mut self, mut docs: Vec<(Arc<Path>, Doc)>) {
+        docs.sort_by(|(path1, _), (path2, _)| path1.cmp(path2));
+        self.postings.clear();
+        self.paths.clear();
+        self.ids.clear();
+        for (id, (path, doc)) in docs.into_iter().enumerate() {
+            let id = id as DocId;
+            for term in doc.tf.keys() {
+                self.postings.entry(term.clone()).or_default().insert(id);
+            }
+            self.paths.push(Some(path.clone()));
+            self.ids.insert(path, id);
+            self.docs.insert(id, doc);
+        }
+    }
+
+    /// Copies the documents and term frequencies of a SQLite index, without
+    /// needing the original files. SQLite does not store term positions, so
+    /// phrase queries match nothing on the result.
+    pub fn from_sqlite(sqlite: &SqliteModel) -> Result<Self, ()> {
+        let mut model = Self::default();
+        for (path, count, tf) in sqlite.documents()? {
+            model.insert_doc(path, Doc { tf, count, ..Doc::default() }, None)?;
+        }
+        Ok(model)
+    }
+
+    /// Read-only snapshot of the index, see [`FrozenModel`]. It ranks with plain
+    /// TF-IDF, so only models with the default configuration apart from their
+    /// lexer can be frozen.
+    pub fn freeze(&self) -> Result<FrozenModel, ()> {
+        if self.config != (Config { lexer: self.config.lexer, ..Config::default() }) {
+            eprintln!("ERROR: only indexes with the default scoring configuration can be frozen, got {config:?}",
+                      config = self.config);
+            return Err(())
+        }
+
+        let mut sorted = self.documents().collect::<Vec<_>>();
+        sorted.sort_by_key(|(path, _)| *path);
+        let mut terms = BTreeMap::<String, Vec<(usize, usize)>>::new();
+        let mut docs = Vec::with_capacity(sorted.len());
+        for (index, (path, doc)) in sorted.into_iter().enumerate() {
+            for (term, freq) in &doc.tf {
+                terms.entry(term.clone()).or_default().push((index, *freq));
+            }
+            docs.push((path.to_path_buf(), doc.count));
+        }
+        Ok(FrozenModel::new(self.config.lexer, docs, terms.into_iter().collect()))
+    }
+
+    /// Same as [`Model::search_query`] for a query tokenized elsewhere: `tokens`
+    /// are taken as they are, so they have to be normalized the way the
+    /// model's lexer would have, e.g. upper case unless case folding is off.
+    pub fn search_tokens(&self, tokens: &[String]) -> Result<Ranking, ()> {
+        let mut tokens = tokens.iter().map(|token| self.resolve_alias(token.clone())).collect();
+        self.collapse_query_terms(&mut tokens);
+        let mut terms = tokens.into_iter().map(|token| (token, 1.0)).collect();
+        self.limit_query_terms(&mut terms)?;
+        self.search_terms(terms)
+    }
+
+    fn search_terms(&self, terms: Vec<(String, f32)>) -> Result<Ranking, ()> {
+        let mut result = if self.config.max_postings_scanned.is_some() {
+            self.search_bounded_terms(&terms)?.0
+        } else {
+            self.rank(&terms)
+        };
+        self.drop_unmatched(&mut result, |doc| contains_any(&terms, doc));
+        Ok(result)
+    }
+
+    /// Leaves out the documents of `ranking` that do not `match` the query
+    /// unless [`InMemoryModel::with_zero_scores`] says otherwise.
+    fn drop_unmatched<F>(&self, ranking: &mut Ranking, matches: F) where F: Fn(&Doc) -> bool {
+        if !self.config.include_zero_scores {
+            ranking.retain(|(path, _)| self.doc(path).is_some_and(&matches));
+        }
+    }
+
+    /// Documents ranked by `scorer` instead of the configured scorer. Those
+    /// containing none of the query terms are left out as by the other
+    /// searches, even if a component such as [`crate::blend::PathMatch`]
+    /// scores them.
+    pub fn search_blended(&self, query: &[char], scorer: &BlendedScorer) -> Result<Ranking, ()> {
+        let terms = self.query_terms(query)?;
+        let mut result = self.documents().map(|(path, doc)| {
+            let stats = DocumentStats {
+                path,
+                tf: &doc.tf,
+                term_count: doc.count,
+                df: &self.df,
+                document_count: self.docs.len(),
+                query_terms: &terms,
+            };
+            (path.to_path_buf(), self.round_score(scorer.score(&stats)))
+        }).collect();
+        sort_ranking(&mut result);
+        self.drop_unmatched(&mut result, |doc| contains_any(&terms, doc));
+        Ok(result)
+    }
+
+    /// Same as [`Model::search_query`], with the documents scored on `threads`
+    /// threads at once.
+    pub fn search_parallel(&self, query: &[char], threads: usize) -> Result<Ranking, ()> {
+        let terms = self.query_terms(query)?;
+        if threads <= 1 || self.config.max_postings_scanned.is_some() || self.config.field_fusion == Some(FieldFusion::ReciprocalRank) {
+            return self.search_query(query)
+        }
+        let docs = self.documents().collect::<Vec<_>>();
+        let chunk_size = docs.len().div_ceil(threads).max(1);
+        let mut result = thread::scope(|scope| {
+            let workers = docs.chunks(chunk_size).map(|chunk| {
+                let terms = &terms;
+                scope.spawn(move || {
+                    chunk.iter().map(|(path, doc)| (path.to_path_buf(), self.score(terms, doc))).collect::<Vec<_>>()
+                })
+            }).collect::<Vec<_>>();
+            workers.into_iter().map(|worker| worker.join()).collect::<Result<Vec<_>, _>>()
+        }).map_err(|_| {
+            eprintln!("ERROR: a search thread panicked");
+        })?.concat();
+        sort_ranking(&mut result);
+        self.drop_unmatched(&mut result, |doc| contains_any(&terms, doc));
+        Ok(result)
+    }
+
+    fn rank(&self, terms: &[(String, f32)]) -> Vec<(PathBuf, f32)> {
+        self.rank_filtered(terms, |_| true)
+    }
+
+    fn rank_filtered<F>(&self, terms: &[(String, f32)], filter: F) -> Vec<(PathBuf, f32)> where F: Fn(&Path) -> bool {
+        if self.config.field_fusion == Some(FieldFusion::ReciprocalRank) && self.config.scorer == Scorer::TfIdf {
+            return self.rank_fused(terms, filter)
+        }
+        let mut result = Vec::<(PathBuf, f32)>::new();
+        for (path, doc) in self.documents() {
+            if !filter(path) {
+                continue
+            }
+            result.push((path.to_path_buf(), self.score(terms, doc)));
+        }
+        sort_ranking(&mut result);
+        result
+    }
+}
+
+/// Best first, ties broken by path so the order is the same on every run.
+pub(crate) fn sort_ranking(ranking: &mut Ranking) {
+    ranking.sort_by(|(path1, rank1), (path2, rank2)| rank2.total_cmp(rank1).then_with(|| path1.cmp(path2)));
+}
+
+/// Ordered by rank, then by path in reverse so that of two documents with the
+/// same rank the one with the smaller path is the greater, matching [`sort_ranking`].
+struct Scored(f32, PathBuf);
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then_with(|| other.1.cmp(&self.1))
+    }
+}
+
+/// Keeps the `k` best ranked documents pushed into it, never holding more.
+/// Which of several documents tied at the cutoff make it is decided by path,
+/// not by the order they were pushed in.
+struct TopK {
+    k: usize,
+    heap: BinaryHeap<Reverse<Scored>>,
+}
+
+impl TopK {
+    fn new(k: usize) -> Self {
+        Self { k, heap: BinaryHeap::new() }
+    }
+
+    fn push(&mut self, path: PathBuf, rank: f32) {
+        self.heap.push(Reverse(Scored(rank, path)));
+        if self.heap.len() > self.k {
+            self.heap.pop();
+        }
+    }
+
+    fn into_ranking(self) -> Ranking {
+        self.heap.into_sorted_vec().into_iter().map(|Reverse(Scored(rank, path))| (path, rank)).collect()
+    }
+}
+
+impl Model for InMemoryModel {
+    fn search_query(&self, query: &[char]) -> Result<Vec<(PathBuf, f32)>, ()> {
+        self.search_terms(self.query_terms(query)?)
+    }
+
+    fn add_document(&mut self, file_path: PathBuf, content: &[char]) -> Result<(), ()> {
+        let document = Self::analyze(self.config.lexer, content);
+        self.add_analyzed(file_path, document)
+    }
+
+    fn estimate_cost(&self, query: &[char]) -> Result<usize, ()> {
+        Ok(self.query_terms(query)?.iter().map(|(term, _)| self.df.get(term).cloned().unwrap_or(0)).sum())
+    }
+
+    fn document_count(&self) -> Result<usize, ()> {
+        Ok(self.docs.len())
+    }
+
+    fn most_frequent_terms(&self, n: usize) -> Result<Vec<(String, usize)>, ()> {
+        let mut terms = self.df.iter().map(|(term, freq)| (term.clone(), *freq)).collect::<Vec<_>>();
+        let by_freq = |(term1, freq1): &(String, usize), (term2, freq2): &(String, usize)| {
+            freq2.cmp(freq1).then_with(|| term1.cmp(term2))
+        };
+        if n < terms.len() {
+            terms.select_nth_unstable_by(n, by_freq);
+            terms.truncate(n);
+        }
+        terms.sort_by(by_freq);
+        Ok(terms)
+    }
+
+    fn remove_documents(&mut self, paths: &[PathBuf]) -> Result<usize, ()> {
+        let mut removed = 0;
+        let mut df_delta = DocFreq::new();
+        for path in paths {
+            let Some(id) = self.ids.remove(path.as_path()) else {
+                continue
+            };
+            self.paths[id as usize] = None;
+            let doc = self.docs.remove(&id).expect("indexed document has statistics");
+            for term in doc.tf.into_keys() {
+                if let Some(postings) = self.postings.get_mut(&term) {
+                    postings.remove(&id);
+                    if postings.is_empty() {
+                        self.postings.remove(&term);
+                    }
+                }
+                *df_delta.entry(term).or_default() += 1;
+            }
+            removed += 1;
+        }
+
+        for (term, delta) in df_delta {
+            if let Some(freq) = self.df.get_mut(&term) {
+                if *freq <= delta {
+                    self.df.remove(&term);
+                } else {
+                    *freq -= delta;
+                }
+            }
+        }
+
+        self.refresh_idf();
+        Ok(removed)
+    }
+
+    fn prewarm_from_queries(&self, queries: &[Vec<char>]) -> Result<(), ()> {
+        if self.config.idf_staleness <= 0.0 {
+            return Ok(())
+        }
+        for query in queries {
+            // A query refused by the limit on query terms needs nothing.
+            let Ok(terms) = self.query_terms(query) else {
+                continue
+            };
+            for (term, _) in terms {
+                self.idf(&term);
+            }
+        }
+        Ok(())
+    }
+
+    fn compact_paths(&mut self) -> Result<(), ()> {
+        if self.paths.len() != self.docs.len() {
+            let docs = self.docs.drain().map(|(id, doc)| {
+                (self.paths[id as usize].take().expect("indexed document has a path"), doc)
+            }).collect();
+            self.reindex(docs);
+        }
+        self.paths.shrink_to_fit();
+        Ok(())
+    }
+}
+
+pub struct SqliteModel {
+    connection: sqlite::Connection,
+}
+
+/// Ranking of [`SqliteModel::search_cursor`] being read from SQLite.
+pub struct SearchCursor<'a> {
+    stmt: Option<sqlite::Statement<'a>>,
+}
+
+impl SearchCursor<'_> {
+    /// The next up to `n` documents of the ranking, fewer once it runs out.
+    pub fn next_page(&mut self, n: usize) -> Result<Ranking, ()> {
+        let mut page = Vec::new();
+        let Some(stmt) = &mut self.stmt else {
+            return Ok(page)
+        };
+        let log_err = |err| {
+            eprintln!("ERROR: could not read search results: {err}");
+        };
+        while page.len() < n {
+            if let sqlite::State::Done = stmt.next().map_err(log_err)? {
+                self.stmt = None;
+                break
+            }
+            let path = PathBuf::from(stmt.read::<String, _>("path").map_err(log_err)?);
+            let score = stmt.read::<f64, _>("score").map_err(log_err)? as f32;
+            page.push((path, score));
+        }
+        Ok(page)
+    }
+}
+
+/// Why [`SqliteModel::open`] failed, the details being reported on stderr.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpenError {
+    /// The file is damaged or not an index at all, see
+    /// [`SqliteModel::open_or_rebuild`].
+    CorruptIndex,
+    Other,
+}
+
+/// How often a cache had what was looked up in it, see
+/// [`SqliteModel::page_cache_stats`] and [`InMemoryModel::idf_cache_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+// Result codes of SQLite for a damaged database and for a file that is not one.
+const SQLITE_CORRUPT: isize = 11;
+const SQLITE_NOTADB: isize = 26;
+
+impl SqliteModel {
+    fn execute(&self, statement: &str) -> Result<(), ()> {
+        self.connection.execute(statement).map_err(|err| {
+            eprintln!("ERROR: could not execute query {statement}: {err}")
+        })?;
+        Ok(())
+    }
+
+    pub fn begin(&self) -> Result<(), ()> {
+        self.execute("BEGIN;")
+    }
+
+    pub fn commit(&self) -> Result<(), ()> {
+        self.execute("COMMIT;")
+    }
+
+    /// Indexes every supported file under `dir_path` within a single
+    /// transaction, skipping the ones that cannot be read or parsed. Returns
+    /// the number of files indexed.
+    pub fn index_directory(&mut self, dir_path: &Path, opts: &IndexOptions) -> Result<usize, ()> {
+        let mut stats = IndexStats::default();
+        self.begin()?;
+        if let Err(()) = add_folder_to_model(dir_path, self, opts, &mut stats) {
+            self.execute("ROLLBACK;").ok();
+            return Err(())
+        }
+        self.commit()?;
+        Ok(stats.indexed)
+    }
+
+    fn migrate(&self) -> Result<(), ()>{
+        self.execute("
+             CREATE TABLE IF NOT EXISTS documents (
+                 id INTEGER NOT NULL PRIMARY KEY,
+                 path TEXT NOT NULL UNIQUE,
+                 term_count INTEGER NOT NULL
+             );
+         ")?;
+
+         self.execute("
+             CREATE TABLE IF NOT EXISTS term_freq (
+                 term TEXT NOT NULL,
+                 doc_id INTEGER NOT NULL,
+                 freq INTEGER NOT NULL,
+                 UNIQUE(term, doc_id),
+                 FOREIGN KEY(doc_id) REFERENCES documents(id)
+             );
+        ")?;
+
+         self.execute("
+             CREATE TABLE IF NOT EXISTS doc_freq (
+                 term TEXT NOT NULL UNIQUE,
+                 freq INTEGER
+             );
+         ")?;
+
+         Ok(())
+    }
+
+    fn doc_freq(&self, term: &str) -> Result<i64, ()> {
+        let query = "SELECT freq FROM doc_freq WHERE term = :term";
+        let log_err = |err| {
+            eprintln!("ERROR: could not prepare or execute query {query}: {err}");
+        };
+        let mut stmt = self.connection.prepare(query).map_err(log_err)?;
+        stmt.bind_iter::<_, (_, sqlite::Value)>([
+            (":term", term.into()),
+        ]).map_err(log_err)?;
+        match stmt.next().map_err(log_err)? {
+            sqlite::State::Row => stmt.read::<i64, _>("freq").map_err(log_err),
+            sqlite::State::Done => Ok(0)
+        }
+    }
+
+    fn remove_documents_batched(&self, paths: &[PathBuf]) -> Result<usize, ()> {
+        self.execute("
+            CREATE TEMP TABLE IF NOT EXISTS removed_documents (
+                id INTEGER NOT NULL PRIMARY KEY
+            );
+            DELETE FROM removed_documents;
+        ")?;
+
+        {
+            let query = "INSERT OR IGNORE INTO removed_documents (id) SELECT id FROM documents WHERE path = :path";
+            let log_err = |err| {
+                eprintln!("ERROR: could not prepare or execute query {query}: {err}");
+            };
+            let mut stmt = self.connection.prepare(query).map_err(log_err)?;
+            for path in paths {
+                stmt.reset().map_err(log_err)?;
+                stmt.bind_iter::<_, (_, sqlite::Value)>([
+                    (":path", path.display().to_string().as_str().into()),
+                ]).map_err(log_err)?;
+                stmt.next().map_err(log_err)?;
+            }
+        }
+
+        let removed = {
+            let query = "SELECT COUNT(*) AS count FROM removed_documents";
+            let log_err = |err| {
+                eprintln!("ERROR: could not prepare or execute query {query}: {err}");
+            };
+            let mut stmt = self.connection.prepare(query).map_err(log_err)?;
+            stmt.next().map_err(log_err)?;
+            stmt.read::<i64, _>("count").map_err(log_err)? as usize
+        };
+
+        self.execute("
+            UPDATE doc_freq SET freq = freq - (
+                SELECT COUNT(*) FROM term_freq
+                WHERE term_freq.term = doc_freq.term AND term_freq.doc_id IN (SELECT id FROM removed_documents)
+            ) WHERE term IN (
+                SELECT term FROM term_freq WHERE doc_id IN (SELECT id FROM removed_documents)
+            );
+            DELETE FROM doc_freq WHERE freq <= 0;
+            DELETE FROM term_freq WHERE doc_id IN (SELECT id FROM removed_documents);
+            DELETE FROM documents WHERE id IN (SELECT id FROM removed_documents);
+        ")?;
+
+        Ok(removed)
+    }
+
+    /// Reads the postings of the terms of `terms` ahead of the queries that
+    /// are expected to need them, pulling their pages into SQLite's and the
+    /// OS's caches. Stops after visiting `max_rows` postings, so a very common
+    /// term cannot hold up startup. Returns the number of postings visited.
+    /// How much it saved shows in [`SqliteModel::page_cache_stats`].
+    pub fn warm(&self, terms: &[&str], max_rows: usize) -> Result<usize, ()> {
+        let query = "
+            SELECT term_freq.doc_id, documents.path, documents.term_count, term_freq.freq
+            FROM term_freq JOIN documents ON documents.id = term_freq.doc_id
+            WHERE term_freq.term = :term
+        ";
+        let log_err = |err| {
+            eprintln!("ERROR: could not prepare or execute query {query}: {err}");
+        };
+        let mut stmt = self.connection.prepare(query).map_err(log_err)?;
+        // Every search counts the documents too.
+        self.document_count()?;
+        let mut visited = 0;
+        for text in terms {
+            for term in Lexer::new(&text.chars().collect::<Vec<_>>()) {
+                self.doc_freq(&term)?;
+                stmt.reset().map_err(log_err)?;
+                stmt.bind_iter::<_, (_, sqlite::Value)>([
+                    (":term", term.as_str().into()),
+                ]).map_err(log_err)?;
+                while visited < max_rows {
+                    match stmt.next().map_err(log_err)? {
+                        sqlite::State::Row => visited += 1,
+                        sqlite::State::Done => break,
+                    }
+                }
+                if visited == max_rows {
+                    return Ok(visited)
+                }
+            }
+        }
+        Ok(visited)
+    }
+
+    /// Hits and misses of SQLite's page cache since the index was opened, a
+    /// miss being a page read from the file, e.g. to see what
+    /// [`SqliteModel::warm`] saves the queries after it.
+    pub fn page_cache_stats(&self) -> Result<CacheStats, ()> {
+        let status = |op| {
+            let (mut current, mut highwater) = (0, 0);
+            // SAFETY: the handle is valid as long as the connection, and the
+            // out pointers as long as the call.
+            let code = unsafe {
+                sqlite3_sys::sqlite3_db_status(self.connection.as_raw(), op, &mut current, &mut highwater, 0)
+            };
+            if code != sqlite3_sys::SQLITE_OK {
+                eprintln!("ERROR: could not read page cache statistics: error code {code}");
+                return Err(())
+            }
+            Ok(current as usize)
+        };
+        Ok(CacheStats {
+            hits: status(sqlite3_sys::SQLITE_DBSTATUS_CACHE_HIT)?,
+            misses: status(sqlite3_sys::SQLITE_DBSTATUS_CACHE_MISS)?,
+        })
+    }
+
+    /// Ranks the documents matching `query` within SQLite itself, returning a
+    /// cursor that reads the ranking page by page instead of all at once.
+    /// SQLite sorts the matches when the first page is read, so the pages are
+    /// a snapshot as of then. Changes made through this connection after that
+    /// are not seen; wrap the paging in [`SqliteModel::begin`] and
+    /// [`SqliteModel::commit`] to also keep other connections' changes out.
+    pub fn search_cursor(&self, query: &[char]) -> Result<SearchCursor<'_>, ()> {
+        let mut weights = HashMap::<String, f32>::new();
+        for term in Lexer::new(query) {
+            *weights.entry(term).or_default() += 1.0;
+        }
+        if weights.is_empty() {
+            return Ok(SearchCursor { stmt: None })
+        }
+
+        let n = self.document_count()?;
+        let mut df = DocFreq::new();
+        for term in weights.keys() {
+            df.insert(term.clone(), self.doc_freq(term)? as usize);
+        }
+
+        let mut params = Vec::<(usize, sqlite::Value)>::new();
+        let mut cases = String::new();
+        for (term, weight) in &weights {
+            let i = params.len();
+            cases.push_str(&format!(" WHEN ?{} THEN ?{}", i + 1, i + 2));
+            params.push((i + 1, term.as_str().into()));
+            params.push((i + 2, ((weight * compute_idf(term, n, &df)) as f64).into()));
+        }
+        let placeholders = (0..weights.len()).map(|i| format!("?{}", 2 * i + 1)).collect::<Vec<_>>().join(", ");
+        let query = format!("
+            SELECT documents.path, SUM((CASE term_freq.term{cases} END) * term_freq.freq / documents.term_count) AS score
+            FROM term_freq JOIN documents ON documents.id = term_freq.doc_id
+            WHERE term_freq.term IN ({placeholders})
+            GROUP BY term_freq.doc_id
+            ORDER BY score DESC, documents.path
+        ");
+        let log_err = |err| {
+            eprintln!("ERROR: could not prepare or execute query {query}: {err}");
+        };
+        let mut stmt = self.connection.prepare(&query).map_err(log_err)?;
+        stmt.bind_iter(params).map_err(log_err)?;
+        Ok(SearchCursor { stmt: Some(stmt) })
+    }
+
+    /// The `k` best documents for `query`. Matching postings are streamed from
+    /// SQLite grouped by document and folded into a heap of at most `k`
+    /// entries, so memory stays bounded however many documents match.
+    pub fn search_top_k(&self, query: &[char], k: usize) -> Result<Ranking, ()> {
+        let mut weights = HashMap::<String, f32>::new();
+        for term in Lexer::new(query) {
+            *weights.entry(term).or_default() += 1.0;
+        }
+        if weights.is_empty() || k == 0 {
+            return Ok(Vec::new())
+        }
+
+        let n = self.document_count()?;
+        let mut df = DocFreq::new();
+        for term in weights.keys() {
+            df.insert(term.clone(), self.doc_freq(term)? as usize);
+        }
+
+        let placeholders = (1..=weights.len()).map(|i| format!("?{i}")).collect::<Vec<_>>().join(", ");
+        let query = format!("
+            SELECT term_freq.doc_id, documents.path, documents.term_count, term_freq.term, term_freq.freq
+            FROM term_freq JOIN documents ON documents.id = term_freq.doc_id
+            WHERE term_freq.term IN ({placeholders})
+            ORDER BY term_freq.doc_id
+        ");
+        let log_err = |err| {
+            eprintln!("ERROR: could not prepare or execute query {query}: {err}");
+        };
+        let mut stmt = self.connection.prepare(&query).map_err(log_err)?;
+        stmt.bind_iter::<_, (_, sqlite::Value)>(
+            weights.keys().enumerate().map(|(i, term)| (i + 1, term.as_str().into()))
+        ).map_err(log_err)?;
+
+        let mut top = TopK::new(k);
+        let mut current: Option<(i64, PathBuf, f32)> = None;
+        while let sqlite::State::Row = stmt.next().map_err(log_err)? {
+            let doc_id = stmt.read::<i64, _>("doc_id").map_err(log_err)?;
+            let term = stmt.read::<String, _>("term").map_err(log_err)?;
+            let freq = stmt.read::<i64, _>("freq").map_err(log_err)? as f32;
+            let count = stmt.read::<i64, _>("term_count").map_err(log_err)? as f32;
+            let contribution = weights[&term] * freq / count * compute_idf(&term, n, &df);
+
+            match &mut current {
+                Some((id, _, rank)) if *id == doc_id => *rank += contribution,
+                _ => {
+                    let path = PathBuf::from(stmt.read::<String, _>("path").map_err(log_err)?);
+                    if let Some((_, path, rank)) = current.replace((doc_id, path, contribution)) {
+                        top.push(path, rank);
+                    }
+                }
+            }
+        }
+        if let Some((_, path, rank)) = current {
+            top.push(path, rank);
+        }
+
+        Ok(top.into_ranking())
+    }
+
+    fn insert_document(&self, file_path: &Path, tf: &TermFreq, count: usize) -> Result<(), ()> {
+        let doc_id = {
+            let query = "INSERT INTO documents (path, term_count) VALUES (:path, :count) RETURNING id";
+            let log_err = |err| {
+                eprintln!("ERROR: could not prepare or execute query {query}: {err}")
+            };
+            let mut stmt = self.connection.prepare(query).map_err(log_err)?;
+
+            stmt.bind_iter::<_, (_, sqlite::Value)>([
+                (":path", file_path.display().to_string().as_str().into()),
+                (":count", (count as i64).into()),
+            ]).map_err(log_err)?;
+
+            match stmt.next().map_err(log_err)? {
+                sqlite::State::Row => stmt.read::<i64, _>("id").map_err(log_err)?,
+                sqlite::State::Done => 0
+            }
+        };
+
+        let term_freq_query = "INSERT INTO term_freq(doc_id, term, freq) VALUES (:doc_id, :term, :freq)";
+        let log_term_freq_err = |err| {
+            eprintln!("ERROR: could not execute or prepare query {term_freq_query}: {err}");
+        };
+        let mut term_freq_stmt = self.connection.prepare(term_freq_query).map_err(log_term_freq_err)?;
+
+        let doc_freq_query = "INSERT INTO doc_freq(term, freq) VALUES (:term, 1) ON CONFLICT(term) DO UPDATE SET freq = freq + 1";
+        let log_doc_freq_err = |err| {
+            eprintln!("ERROR: could not execute or prepare query {doc_freq_query}: {err}");
+        };
+        let mut doc_freq_stmt = self.connection.prepare(doc_freq_query).map_err(log_doc_freq_err)?;
+
+        for (term, freq) in tf {
+            term_freq_stmt.reset().map_err(log_term_freq_err)?;
+            term_freq_stmt.bind_iter::<_, (_, sqlite::Value)>([
+                (":doc_id", doc_id.into()),
+                (":term", term.as_str().into()),
+                (":freq", (*freq as i64).into()),
+            ]).map_err(log_term_freq_err)?;
+            term_freq_stmt.next().map_err(log_term_freq_err)?;
+
+            doc_freq_stmt.reset().map_err(log_doc_freq_err)?;
+            doc_freq_stmt.bind_iter::<_, (_, sqlite::Value)>([
+                (":term", term.as_str().into()),
+            ]).map_err(log_doc_freq_err)?;
+            doc_freq_stmt.next().map_err(log_doc_freq_err)?;
+        }
+
+        Ok(())
+    }
+
+    /// Every indexed document with its term count and term frequencies.
+    fn documents(&self) -> Result<Vec<(PathBuf, usize, TermFreq)>, ()> {
+        let mut documents = HashMap::<i64, (PathBuf, usize, TermFreq)>::new();
+        {
+            let query = "SELECT id, path, term_count FROM documents";
+            let log_err = |err| {
+                eprintln!("ERROR: could not prepare or execute query {query}: {err}");
+            };
+            let mut stmt = self.connection.prepare(query).map_err(log_err)?;
+            while let sqlite::State::Row = stmt.next().map_err(log_err)? {
+                let id = stmt.read::<i64, _>("id").map_err(log_err)?;
+                let path = PathBuf::from(stmt.read::<String, _>("path").map_err(log_err)?);
+                let count = stmt.read::<i64, _>("term_count").map_err(log_err)? as usize;
+                documents.insert(id, (path, count, TermFreq::new()));
+            }
+        }
+
+        let query = "SELECT doc_id, term, freq FROM term_freq";
+        let log_err = |err| {
+            eprintln!("ERROR: could not prepare or execute query {query}: {err}");
+        };
+        let mut stmt = self.connection.prepare(query).map_err(log_err)?;
+        while let sqlite::State::Row = stmt.next().map_err(log_err)? {
+            let doc_id = stmt.read::<i64, _>("doc_id").map_err(log_err)?;
+            let term = stmt.read::<String, _>("term").map_err(log_err)?;
+            let freq = stmt.read::<i64, _>("freq").map_err(log_err)? as usize;
+            if let Some((_, _, tf)) = documents.get_mut(&doc_id) {
+                tf.insert(term, freq);
+            }
+        }
+
+        Ok(documents.into_values().collect())
+    }
+
+    /// Writes the documents of an in-memory index to a new SQLite database at
+    /// `path`. The SQLite backend only knows the default configuration, so
+    /// models built with any other are rejected rather than converted into an
+    /// index that answers queries differently.
+    pub fn from_in_memory(model: &InMemoryModel, path: &Path) -> Result<Self, ()> {
+        if *model.config() != Config::default() {
+            eprintln!("ERROR: only indexes with the default configuration can be stored in SQLite, got {config:?}",
+                      config = model.config());
+            return Err(())
+        }
+
+        let this = Self::open(path).map_err(|_| ())?;
+        this.begin()?;
+        for (file_path, doc) in model.documents() {
+            if let Err(()) = this.insert_document(file_path, &doc.tf, doc.count) {
+                this.execute("ROLLBACK;").ok();
+                return Err(())
+            }
+        }
+        this.commit()?;
+        Ok(this)
+    }
+
+    /// Opens the index at `path`, creating it if it does not exist. A file
+    /// that is not an SQLite database or fails its integrity check is reported
+    /// as [`OpenError::CorruptIndex`], such an index can only be rebuilt.
+    pub fn open(path: &Path) -> Result<Self, OpenError> {
+        let connection = sqlite::open(path).map_err(|err| {
+            eprintln!("ERROR: could not open sqlite database {path}: {err}", path = path.display());
+            OpenError::Other
+        })?;
+
+        let this = Self {connection};
+
+        this.check_integrity(path)?;
+        this.migrate().map_err(|err| {
+            eprintln!("ERROR: error occured during migration {err:?})");
+            OpenError::Other
+        })?;
+
+        Ok(this)
+    }
+
+    /// Same as [`SqliteModel::open`], except that a corrupted index is deleted
+    /// and indexed anew by `rebuild` from an empty one. The journal and
+    /// write-ahead log next to it belong to the corrupted index, so they are
+    /// deleted as well.
+    pub fn open_or_rebuild<F>(path: &Path, rebuild: F) -> Result<Self, ()> where F: FnOnce(&mut Self) -> Result<(), ()> {
+        match Self::open(path) {
+            Ok(this) => Ok(this),
+            Err(OpenError::CorruptIndex) => {
+                for suffix in ["", "-journal", "-wal", "-shm"] {
+                    let mut file = path.as_os_str().to_owned();
+                    file.push(suffix);
+                    let file = PathBuf::from(file);
+                    match fs::remove_file(&file) {
+                        Err(err) if err.kind() != ErrorKind::NotFound => {
+                            eprintln!("ERROR: could not delete file {file}: {err}", file = file.display());
+                            return Err(())
+                        }
+                        _ => {}
+                    }
+                }
+                let mut this = Self::open(path).map_err(|_| ())?;
+                rebuild(&mut this)?;
+                Ok(this)
+            }
+            Err(OpenError::Other) => Err(()),
+        }
+    }
+
+    fn check_integrity(&self, path: &Path) -> Result<(), OpenError> {
+        let query = "PRAGMA quick_check";
+        let log_err = |err: sqlite::Error| {
+            if matches!(err.code, Some(SQLITE_CORRUPT | SQLITE_NOTADB)) {
+                eprintln!("ERROR: sqlite database {path} is corrupted: {err}", path = path.display());
+                OpenError::CorruptIndex
+            } else {
+                eprintln!("ERROR: could not prepare or execute query {query}: {err}");
+                OpenError::Other
+            }
+        };
+        let mut stmt = self.connection.prepare(query).map_err(log_err)?;
+        while let sqlite::State::Row = stmt.next().map_err(log_err)? {
+            let problem = stmt.read::<String, _>(0).map_err(log_err)?;
+            if problem != "ok" {
+                eprintln!("ERROR: sqlite database {path} is corrupted: {problem}", path = path.display());
+                return Err(OpenError::CorruptIndex)
+            }
+        }
+        Ok(())
+    }
+}
+
+
+impl Model for SqliteModel {
+    fn search_query(&self, query: &[char]) -> Result<Vec<(PathBuf, f32)>, ()> {
+        self.search_top_k(query, usize::MAX)
+    }
+
+    fn add_document(&mut self, file_path: PathBuf, content: &[char]) -> Result<(), ()> {
+        let (tf, count) = term_frequencies(content);
+        self.insert_document(&file_path, &tf, count)
+    }
+
+    fn estimate_cost(&self, query: &[char]) -> Result<usize, ()> {
+        let mut cost = 0;
+        for term in Lexer::new(query) {
+            cost += self.doc_freq(&term)? as usize;
+        }
+        Ok(cost)
+    }
+
+    fn document_count(&self) -> Result<usize, ()> {
+        let query = "SELECT COUNT(*) AS count FROM documents";
+        let log_err = |err| {
+            eprintln!("ERROR: could not prepare or execute query {query}: {err}");
+        };
+        let mut stmt = self.connection.prepare(query).map_err(log_err)?;
+        stmt.next().map_err(log_err)?;
+        Ok(stmt.read::<i64, _>("count").map_err(log_err)? as usize)
+    }
+
+    fn most_frequent_terms(&self, n: usize) -> Result<Vec<(String, usize)>, ()> {
+        let query = "SELECT term, freq FROM doc_freq ORDER BY freq DESC, term LIMIT :n";
+        let log_err = |err| {
+            eprintln!("ERROR: could not prepare or execute query {query}: {err}");
+        };
+        let mut stmt = self.connection.prepare(query).map_err(log_err)?;
+        stmt.bind_iter::<_, (_, sqlite::Value)>([
+            (":n", (n.min(i64::MAX as usize) as i64).into()),
+        ]).map_err(log_err)?;
+        let mut terms = Vec::new();
+        while let sqlite::State::Row = stmt.next().map_err(log_err)? {
+            let term = stmt.read::<String, _>("term").map_err(log_err)?;
+            let freq = stmt.read::<i64, _>("freq").map_err(log_err)? as usize;
+            terms.push((term, freq));
+        }
+        Ok(terms)
+    }
+
+    fn prewarm_from_queries(&self, queries: &[Vec<char>]) -> Result<(), ()> {
+        let terms = queries.iter().flat_map(|query| Lexer::new(query)).collect::<BTreeSet<_>>();
+        self.warm(&terms.iter().map(String::as_str).collect::<Vec<_>>(), usize::MAX)?;
+        Ok(())
+    }
+
+    fn remove_documents(&mut self, paths: &[PathBuf]) -> Result<usize, ()> {
+        // A savepoint rather than BEGIN so that it also works within a
+        // transaction opened by the caller.
+        self.execute("SAVEPOINT remove_documents;")?;
+        let result = self.remove_documents_batched(paths);
+        if result.is_ok() {
+            self.execute("RELEASE remove_documents;")?;
+        } else {
+            self.execute("ROLLBACK TO remove_documents;")?;
+            self.execute("RELEASE remove_documents;")?;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    fn model_of(docs: &[(&str, &str)]) -> InMemoryModel {
+        let mut model = InMemoryModel::default();
+        for (path, content) in docs {
+            model.add_document(PathBuf::from(path), &chars(content)).unwrap();
+        }
+        model
+    }
+
+    /// A file or directory in the temporary directory, deleted when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("searust-{pid}-{name}", pid = std::process::id()));
+            Self::remove(&path);
+            Self(path)
+        }
+
+        fn remove(path: &Path) {
+            fs::remove_file(path).or_else(|_| fs::remove_dir_all(path)).ok();
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            Self::remove(&self.0);
+        }
+    }
+
+    fn save_and_load(model: &InMemoryModel, name: &str) -> InMemoryModel {
+        let file = TempFile::new(name);
+        fs::write(&file.0, serde_json::to_string(model).unwrap()).unwrap();
+        InMemoryModel::load(&file.0, None).unwrap()
+    }
+
+    const DEEP: &str = "/srv/data/projects/searust/archive/2023/reports/quarterly/department/finance/drafts";
+
+    fn deep_corpus(n: usize) -> Vec<(String, String)> {
+        (0..n).map(|i| (format!("{DEEP}/{i}.txt"), format!("report budget q{q} item{i} draft{d}", q = i % 4, d = i % 3))).collect()
+    }
+
+    #[test]
+    fn interned_ids_leave_results_unchanged() {
+        let corpus = deep_corpus(40);
+        let mut model = InMemoryModel::default();
+        for (path, content) in &corpus {
+            model.add_document(PathBuf::from(path), &chars(content)).unwrap();
+        }
+        let removed = [PathBuf::from(&corpus[3].0), PathBuf::from(&corpus[10].0)];
+        assert_eq!(model.remove_documents(&removed).unwrap(), 2);
+
+        let kept = corpus.iter().enumerate()
+            .filter(|(i, _)| *i != 3 && *i != 10)
+            .map(|(_, (path, content))| (path.as_str(), content.as_str()))
+            .collect::<Vec<_>>();
+        let fresh = model_of(&kept);
+        let query = chars("budget q3 draft1");
+        let expected = fresh.search_query(&query).unwrap();
+        assert_eq!(model.search_query(&query).unwrap(), expected);
+
+        model.compact_paths().unwrap();
+        assert_eq!(model.paths.len(), kept.len());
+        assert_eq!(model.search_query(&query).unwrap(), expected);
+        assert_eq!(model.nearest_documents(Path::new(&corpus[8].0), 3).unwrap(),
+                   fresh.nearest_documents(Path::new(&corpus[8].0), 3).unwrap());
+    }
+
+    #[test]
+    fn paths_are_stored_once() {
+        let corpus = deep_corpus(200);
+        let mut model = InMemoryModel::default();
+        for (path, content) in &corpus {
+            model.add_document(PathBuf::from(path), &chars(content)).unwrap();
+        }
+
+        // The lookup by path shares the allocations of the table of paths.
+        for (path, id) in &model.ids {
+            assert!(Arc::ptr_eq(path, model.paths[*id as usize].as_ref().unwrap()));
+        }
+        let stored = model.paths.iter().flatten().map(|path| path.as_os_str().len()).sum::<usize>();
+        // Keying the documents and postings by path would store one copy of
+        // the path for every document and for every one of its terms.
+        let by_path = model.docs.iter()
+            .map(|(id, doc)| (doc.tf.len() + 1) * model.path(*id).as_os_str().len())
+            .sum::<usize>();
+        assert_eq!(stored, corpus.iter().map(|(path, _)| path.len()).sum::<usize>());
+        assert!(stored * 6 <= by_path, "{stored} bytes of paths, {by_path} if keyed by path");
+    }
+
+    #[test]
+    fn updating_a_document_reuses_its_id() {
+        let mut model = model_of(&[("a.txt", "alpha"), ("b.txt", "beta")]);
+        for content in ["alpha gamma", "alpha delta", "alpha"] {
+            model.add_document(PathBuf::from("a.txt"), &chars(content)).unwrap();
+        }
+        assert_eq!(model.paths.len(), 2);
+        assert_eq!(model.search_query(&chars("alpha")).unwrap().len(), 1);
+        assert!(model.search_query(&chars("delta")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn interned_ids_survive_a_round_trip() {
+        let mut model = model_of(&[("/x/a.txt", "alpha beta"), ("/x/b.txt", "beta gamma"), ("/x/c.txt", "gamma")]);
+        model.remove_document(Path::new("/x/b.txt")).unwrap();
+        let loaded = save_and_load(&model, "interned-ids.json");
+        assert_eq!(loaded.search_query(&chars("gamma alpha")).unwrap(), model.search_query(&chars("gamma alpha")).unwrap());
+        assert_eq!(loaded.paths, model.paths);
+
+        let mut loaded = loaded;
+        loaded.add_document(PathBuf::from("/x/c.txt"), &chars("delta")).unwrap();
+        assert_eq!(loaded.paths.len(), 3);
+        assert_eq!(loaded.search_query(&chars("delta")).unwrap()[0].0, PathBuf::from("/x/c.txt"));
+    }
+
+    #[test]
+    fn loads_documents_keyed_by_path() {
+        let json = r#"{"format_version": 1, "docs": {
+            "b.txt": {"tf": {"BETA": 1}, "count": 1, "id": 0},
+            "a.txt": {"tf": {"ALPHA": 1, "BETA": 1}, "count": 2, "id": 1}
+        }, "df": {"ALPHA": 1, "BETA": 2}}"#;
+        let file = TempFile::new("docs-by-path.json");
+        fs::write(&file.0, json).unwrap();
+        let model = InMemoryModel::load(&file.0, None).unwrap();
+        assert_eq!(model.paths, [Some(Arc::from(Path::new("a.txt"))), Some(Arc::from(Path::new("b.txt")))]);
+        assert_eq!(model.search_query(&chars("alpha")).unwrap(), [(PathBuf::from("a.txt"), 0.5 * 2f32.log10())]);
+    }
+
+    #[test]
+    fn search_results_report_length_and_matched_terms() {
+        let model = model_of(&[("a.txt", "rust search engine rust written here"), ("b.txt", "python"), ("c.txt", "other")]);
+        let results = model.search_results(&chars("rust engine python rust")).unwrap();
+        let ranking = model.search_query(&chars("rust engine python rust")).unwrap();
+        assert_eq!(results.iter().map(|result| (result.path.clone(), result.score)).collect::<Vec<_>>(), ranking);
+        let a = results.iter().find(|result| result.path == Path::new("a.txt")).unwrap();
+        assert_eq!((a.term_count, a.matched_terms), (6, 2));
+        let b = results.iter().find(|result| result.path == Path::new("b.txt")).unwrap();
+        assert_eq!((b.term_count, b.matched_terms), (1, 1));
+        assert_eq!(a.debug, None);
+    }
+
+    fn numbered_corpus() -> InMemoryModel {
+        model_of(&[("a.txt", "one two three"), ("b.txt", "four five six"), ("c.txt", "seven")])
+    }
+
+    #[test]
+    fn queries_over_the_term_cap_are_truncated() {
+        let model = numbered_corpus().with_max_query_terms(2, QueryTermOverflow::Truncate);
+        assert_eq!(model.search_query(&chars("one two four")).unwrap(), numbered_corpus().search_query(&chars("one two")).unwrap());
+        assert_eq!(model.search_tokens(&["ONE".to_string(), "TWO".to_string(), "FOUR".to_string()]).unwrap(),
+                   numbered_corpus().search_query(&chars("one two")).unwrap());
+    }
+
+    #[test]
+    fn queries_over_the_term_cap_are_refused() {
+        let model = numbered_corpus().with_max_query_terms(2, QueryTermOverflow::Error);
+        assert!(model.search_query(&chars("one two four")).is_err());
+        assert!(model.search_tokens(&["ONE".to_string(), "TWO".to_string(), "FOUR".to_string()]).is_err());
+        assert!(model.search_bounded(&chars("one two four")).is_err());
+        assert!(model.search_with_confidence(&chars("one two four")).is_err());
+        // Refused whatever the policy, since dropping a term may leave a NOT
+        // negating nothing.
+        let query = Query::term("one").and(Query::term("two")).not(Query::term("four"));
+        assert!(model.search_boolean(&query).is_err());
+        let model = numbered_corpus().with_max_query_terms(2, QueryTermOverflow::Truncate);
+        assert!(model.search_boolean(&query).is_err());
+    }
+
+    #[test]
+    fn queries_under_the_term_cap_are_unaffected() {
+        let capped = numbered_corpus().with_max_query_terms(3, QueryTermOverflow::Error);
+        let uncapped = numbered_corpus();
+        for query in ["one two four", "the one", "seven"] {
+            assert_eq!(capped.search_query(&chars(query)).unwrap(), uncapped.search_query(&chars(query)).unwrap());
+        }
+        let query = Query::term("one").or(Query::term("four")).not(Query::term("two"));
+        assert_eq!(capped.search_boolean(&query).unwrap(), uncapped.search_boolean(&query).unwrap());
+    }
+
+    fn paths(ranking: &[(PathBuf, f32)]) -> Vec<&str> {
+        ranking.iter().map(|(path, _)| path.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn only_documents_with_a_query_term_are_returned() {
+        let model = model_of(&[("a.txt", "apple pie"), ("b.txt", "banana"), ("c.txt", "apple tart")]);
+        assert_eq!(paths(&model.search_query(&chars("apple")).unwrap()), ["a.txt", "c.txt"]);
+        let model = model.with_zero_scores(true);
+        assert_eq!(paths(&model.search_query(&chars("apple")).unwrap()), ["a.txt", "c.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn documents_with_a_term_found_everywhere_are_returned() {
+        let model = model_of(&[("only.txt", "lonely document")]);
+        assert_eq!(model.search_query(&chars("lonely")).unwrap(), [(PathBuf::from("only.txt"), 0.0)]);
+
+        let model = model_of(&[("a.txt", "common apple"), ("b.txt", "common banana"), ("c.txt", "cherry")]);
+        let model = model.with_field_fusion(FieldFusion::WeightedSum, HashMap::new());
+        assert_eq!(paths(&model.search_query(&chars("common")).unwrap()), ["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn zero_scores_are_dropped_like_sqlite_does() {
+        let model = model_of(&[("a.txt", "everywhere apple"), ("b.txt", "everywhere banana")]);
+        let file = TempFile::new("zero-scores.db");
+        let sqlite = SqliteModel::from_in_memory(&model, &file.0).unwrap();
+        for query in ["everywhere", "apple", "everywhere banana", "missing"] {
+            assert_eq!(model.search_query(&chars(query)).unwrap(), sqlite.search_query(&chars(query)).unwrap(), "{query}");
+        }
+        // A term in every document has an IDF of 0, yet its documents match it.
+        let ranking = model.search_query(&chars("everywhere")).unwrap();
+        assert_eq!(ranking, [(PathBuf::from("a.txt"), 0.0), (PathBuf::from("b.txt"), 0.0)]);
+        assert!(model.search_query(&chars("missing")).unwrap().is_empty());
+        assert_eq!(model.with_zero_scores(true).search_query(&chars("missing")).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn every_search_drops_unmatched_documents() {
+        let mut model = InMemoryModel::default();
+        for (path, title, body) in [("a.txt", "common apple", "text"), ("b.txt", "common", "apple text"), ("c.txt", "cherry", "text")] {
+            model.add_document_with_fields(PathBuf::from(path), &[("title", &chars(title)), ("body", &chars(body))]).unwrap();
+        }
+        let query = chars("common apple");
+        assert_eq!(paths(&model.search_excluding(&query, &HashSet::from([PathBuf::from("a.txt")]), 10).unwrap()), ["b.txt"]);
+        let confident = model.search_with_confidence(&query).unwrap();
+        assert_eq!(confident.iter().map(|(path, _, _)| path.to_str().unwrap()).collect::<Vec<_>>(), ["a.txt", "b.txt"]);
+        assert_eq!(paths(&model.score_topic(&[chars("apple"), chars("common")], TopicCombine::Max, 10).unwrap()), ["a.txt", "b.txt"]);
+        assert_eq!(paths(&model.search_field(&chars("apple"), "title").unwrap()), ["a.txt"]);
+        assert_eq!(paths(&model.search_in_fields(&chars("apple"), &["body"]).unwrap()), ["b.txt"]);
+        assert_eq!(paths(&model.search_blended(&query, &BlendedScorer::new().with(crate::blend::TfIdf, 1.0)).unwrap()), ["a.txt", "b.txt"]);
+        assert_eq!(paths(&model.search_bounded(&query).unwrap().0), ["a.txt", "b.txt"]);
+        assert_eq!(paths(&model.search_query_acl(&query, &HashSet::new(), 10).unwrap()), ["a.txt", "b.txt"]);
+        assert_eq!(paths(&model.search_parallel(&query, 2).unwrap()), ["a.txt", "b.txt"]);
+
+        let model = model.with_zero_scores(true);
+        assert_eq!(model.search_field(&chars("apple"), "title").unwrap().len(), 3);
+        assert_eq!(model.score_topic(&[chars("apple")], TopicCombine::Mean, 10).unwrap().len(), 3);
+    }
+
+    fn javascript_corpus() -> InMemoryModel {
+        model_of(&[("a.txt", "js js code"), ("b.txt", "javascript code"), ("c.txt", "js javascript"), ("d.txt", "python")])
+    }
+
+    #[test]
+    fn merged_terms_are_found_under_either_name() {
+        let mut model = javascript_corpus();
+        model.merge_terms("js", "javascript").unwrap();
+        assert_eq!(model.df.get("JS"), None);
+        assert_eq!(model.df["JAVASCRIPT"], 3);
+        assert_eq!(model.explain(&chars("javascript"), Path::new("a.txt")).unwrap().terms[0].tf, 2.0 / 3.0);
+        assert_eq!(model.explain(&chars("javascript"), Path::new("c.txt")).unwrap().terms[0].tf, 1.0);
+
+        let union = model.search_query(&chars("javascript")).unwrap();
+        assert_eq!(paths(&union), ["c.txt", "a.txt", "b.txt"]);
+        assert_eq!(model.search_query(&chars("js")).unwrap(), union);
+        assert_eq!(model.search_tokens(&["JS".to_string()]).unwrap(), union);
+        assert_eq!(model.search_boolean(&Query::term("js")).unwrap(), union);
+        assert_eq!(model.phrase_document_frequency(&chars("js js")), 2);
+
+        model.add_document(PathBuf::from("e.txt"), &chars("js")).unwrap();
+        assert_eq!(model.df["JAVASCRIPT"], 4);
+        assert_eq!(model.search_query(&chars("js")).unwrap().len(), 4);
+        let loaded = save_and_load(&model, "merged-terms.json");
+        assert_eq!(loaded.search_query(&chars("js")).unwrap(), model.search_query(&chars("js")).unwrap());
+    }
+
+    #[test]
+    fn merging_follows_earlier_merges() {
+        let mut model = javascript_corpus();
+        model.merge_terms("js", "javascript").unwrap();
+        model.merge_terms("javascript", "code").unwrap();
+        assert_eq!(model.df["CODE"], 3);
+        assert_eq!(model.search_query(&chars("js")).unwrap(), model.search_query(&chars("code")).unwrap());
+        assert!(model.merge_terms("js", "python").is_err());
+        assert!(model.merge_terms("a b", "c").is_err());
+    }
+
+    #[test]
+    fn vocabulary_stays_at_the_cap() {
+        let mut model = InMemoryModel::default().with_max_vocabulary(Some(5));
+        for i in 0..20 {
+            model.add_document(PathBuf::from(format!("{i}.txt")), &chars(&format!("alpha beta rare{i} unique{i}"))).unwrap();
+            assert!(model.df.len() <= 5);
+        }
+        assert_eq!(model.df.len(), 5);
+        // Only the rarest terms went, the last document's ones being kept.
+        assert_eq!((model.df["ALPHA"], model.df["BETA"]), (20, 20));
+        assert!(model.df.contains_key("RARE19") && model.df.contains_key("UNIQUE19"));
+        assert!(!model.df.contains_key("RARE0"));
+        assert!(model.postings.keys().all(|term| model.df.contains_key(term)));
+        assert!(model.docs.values().all(|doc| doc.tf.keys().all(|term| model.df.contains_key(term))));
+
+        assert_eq!(model.search_query(&chars("alpha")).unwrap().len(), 20);
+        assert_eq!(paths(&model.search_query(&chars("rare19 alpha")).unwrap())[0], "19.txt");
+        assert!(model.search_query(&chars("rare0")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn recurring_terms_are_not_evicted_on_arrival() {
+        let mut model = InMemoryModel::default().with_max_vocabulary(Some(2));
+        for (path, content) in [("1.txt", "zebra xylophone"), ("2.txt", "apple yak"), ("3.txt", "apple zucchini")] {
+            model.add_document(PathBuf::from(path), &chars(content)).unwrap();
+        }
+        assert_eq!(model.df.get("APPLE"), Some(&2));
+        assert_eq!(model.df.get("ZEBRA"), None);
+    }
+
+    #[test]
+    fn evicted_terms_are_gone_from_fields() {
+        let mut model = InMemoryModel::default()
+            .with_max_vocabulary(Some(2))
+            .with_field_fusion(FieldFusion::WeightedSum, HashMap::new());
+        model.add_document_with_fields(PathBuf::from("a.txt"), &[("title", &chars("common")), ("body", &chars("rare"))]).unwrap();
+        model.add_document_with_fields(PathBuf::from("b.txt"), &[("title", &chars("common")), ("body", &chars("other"))]).unwrap();
+        assert_eq!(model.df.get("RARE"), None);
+        assert!(model.search_query(&chars("rare")).unwrap().is_empty());
+        assert!(model.search_field(&chars("rare"), "body").unwrap().is_empty());
+        assert!(model.docs.values().flat_map(|doc| doc.fields.values()).all(|field| !field.tf.contains_key("RARE")));
+    }
+
+    fn sqlite_corpus(file: &TempFile) -> SqliteModel {
+        let mut model = SqliteModel::open(&file.0).unwrap();
+        model.begin().unwrap();
+        for i in 0..500 {
+            let content = format!("filler{i} text{t} {rare}", t = i % 50, rare = if i % 100 == 0 { "rust" } else { "" });
+            model.add_document(PathBuf::from(format!("/docs/{i}.txt")), &chars(&content)).unwrap();
+        }
+        model.commit().unwrap();
+        model
+    }
+
+    #[test]
+    fn warm_searches_read_fewer_pages() {
+        let file = TempFile::new("warm.db");
+        drop(sqlite_corpus(&file));
+        let model = SqliteModel::open(&file.0).unwrap();
+
+        let misses_of_search = |model: &SqliteModel| {
+            let before = model.page_cache_stats().unwrap();
+            assert_eq!(model.search_query(&chars("rust")).unwrap().len(), 5);
+            model.page_cache_stats().unwrap().misses - before.misses
+        };
+        // Opening reads the whole file to check it, so empty the cache.
+        model.execute("PRAGMA shrink_memory;").unwrap();
+        let cold = misses_of_search(&model);
+
+        model.execute("PRAGMA shrink_memory;").unwrap();
+        assert_eq!(model.warm(&["rust"], usize::MAX).unwrap(), 5);
+        let warm = misses_of_search(&model);
+        assert!(cold > 0);
+        assert!(warm < cold, "{warm} pages read warm, {cold} cold");
+    }
+
+    #[test]
+    fn warming_stops_at_the_row_budget() {
+        let file = TempFile::new("warm-budget.db");
+        let model = sqlite_corpus(&file);
+        assert_eq!(model.warm(&["text1"], 3).unwrap(), 3);
+        assert_eq!(model.warm(&["text1 rust"], 100).unwrap(), 15);
+        assert_eq!(model.warm(&["missing"], 100).unwrap(), 0);
+    }
+
+    #[test]
+    fn prewarmed_searches_hit_the_idf_cache() {
+        let mut model = InMemoryModel::default().with_idf_staleness(0.5);
+        for (path, content) in [("a.txt", "rust search"), ("b.txt", "eggs bacon"), ("c.txt", "rust eggs")] {
+            model.add_document(PathBuf::from(path), &chars(content)).unwrap();
+        }
+        let log = [chars("rust"), chars("eggs search"), chars("missing")];
+        model.prewarm_from_queries(&log).unwrap();
+        let warmed = model.idf_cache_stats();
+        assert_eq!(warmed, CacheStats { hits: 0, misses: 4 });
+
+        for query in &log[..2] {
+            assert!(!model.search_query(query).unwrap().is_empty());
+        }
+        let searched = model.idf_cache_stats();
+        assert!(searched.hits > 0);
+        // Only the term that is not indexed is computed again.
+        assert_eq!(searched.misses, warmed.misses);
+        model.search_query(&log[2]).unwrap();
+        assert!(model.idf_cache_stats().misses > warmed.misses);
+    }
+
+    #[test]
+    fn cold_searches_miss_the_idf_cache() {
+        let mut model = InMemoryModel::default().with_idf_staleness(0.5);
+        model.add_document(PathBuf::from("a.txt"), &chars("rust search")).unwrap();
+        model.add_document(PathBuf::from("b.txt"), &chars("eggs")).unwrap();
+        model.search_query(&chars("rust")).unwrap();
+        assert_eq!(model.idf_cache_stats().misses, 1);
+
+        let exact = model_of(&[("a.txt", "rust search"), ("b.txt", "eggs")]);
+        exact.prewarm_from_queries(&[chars("rust")]).unwrap();
+        exact.search_query(&chars("rust")).unwrap();
+        assert_eq!(exact.idf_cache_stats(), CacheStats::default());
+    }
+
+    #[test]
+    fn prewarmed_sqlite_searches_read_no_pages() {
+        let file = TempFile::new("prewarm.db");
+        drop(sqlite_corpus(&file));
+        let model = SqliteModel::open(&file.0).unwrap();
+        model.execute("PRAGMA shrink_memory;").unwrap();
+        let log = [chars("rust"), chars("text7 filler3")];
+        model.prewarm_from_queries(&log).unwrap();
+        let warmed = model.page_cache_stats().unwrap();
+        for query in &log {
+            assert!(!model.search_query(query).unwrap().is_empty());
+        }
+        let searched = model.page_cache_stats().unwrap();
+        assert_eq!(searched.misses, warmed.misses);
+        assert!(searched.hits > warmed.hits);
+    }
+
+    #[test]
+    fn repeated_query_terms_weigh_more() {
+        let model = model_of(&[("rust.txt", "rust borrowck"), ("python.txt", "python interpreter"), ("c.txt", "pointers")]);
+        let once = model.explain(&chars("rust python"), Path::new("rust.txt")).unwrap();
+        let twice = model.explain(&chars("rust rust python"), Path::new("rust.txt")).unwrap();
+        assert_eq!((once.terms[0].weight, twice.terms[0].weight), (1.0, 2.0));
+        assert_eq!(twice.terms[0].contribution, 2.0 * once.terms[0].contribution);
+        assert!(twice.terms[0].contribution > 0.0);
+
+        // The repetition makes the rust document outrank the python one it tied with.
+        assert_eq!(model.search_query(&chars("rust python")).unwrap()[0].1, model.search_query(&chars("rust python")).unwrap()[1].1);
+        let ranking = model.search_query(&chars("rust rust python")).unwrap();
+        assert_eq!(paths(&ranking), ["rust.txt", "python.txt"]);
+        assert_eq!(ranking[0].1, 2.0 * ranking[1].1);
+
+        let file = TempFile::new("repeated-terms.db");
+        let sqlite = SqliteModel::from_in_memory(&model, &file.0).unwrap();
+        assert_eq!(paths(&sqlite.search_query(&chars("rust rust python")).unwrap()), ["rust.txt", "python.txt"]);
+        assert_eq!(paths(&model.freeze().unwrap().search_query(&chars("rust rust python"))), ["rust.txt", "python.txt"]);
+    }
+
+    #[test]
+    fn substring_fallback_finds_tokens_inside_terms() {
+        let docs = [("config.txt", "load the config"), ("notes.txt", "a plain file")];
+        assert!(model_of(&docs).search_query(&chars("fig")).unwrap().is_empty());
+
+        let model = model_of(&docs).with_substring_fallback(true);
+        let ranking = model.search_query(&chars("fig")).unwrap();
+        assert_eq!(paths(&ranking), ["config.txt"]);
+        assert!(ranking[0].1 > 0.0);
+        // Once a query token is in the vocabulary, the others are not matched as substrings.
+        assert_eq!(paths(&model.search_query(&chars("file fig")).unwrap()), ["notes.txt"]);
+    }
+
+    #[test]
+    fn phrase_document_frequency_counts_consecutive_terms() {
+        let model = model_of(&[("a.txt", "new york city"), ("b.txt", "i love new york"), ("c.txt", "new jersey york")]);
+        assert_eq!(model.phrase_document_frequency(&chars("new york")), 2);
+        assert_eq!(model.df["NEW"], 3);
+        assert_eq!(model.phrase_document_frequency(&chars("york new")), 0);
+    }
+
+    #[test]
+    fn confidence_is_high_for_rare_terms_and_low_for_common_ones() {
+        let mut docs = vec![("rare.txt".to_string(), "zebra quokka".to_string())];
+        docs.extend((0..9).map(|i| (format!("{i}.txt"), format!("common filler{i}"))));
+        let docs = docs.iter().map(|(path, content)| (path.as_str(), content.as_str())).collect::<Vec<_>>();
+        let calibrations = [Calibration::MaxScore, Calibration::Sigmoid { midpoint: 0.5, steepness: 10.0 }];
+        for calibration in calibrations {
+            let model = model_of(&docs).with_calibration(calibration);
+            let results = model.search_with_confidence(&chars("zebra quokka common")).unwrap();
+            let confidence = |path: &str| results.iter().find(|(other, _, _)| other == Path::new(path)).unwrap().2;
+            assert!(confidence("rare.txt") > 0.9, "{calibration:?}: {results:?}");
+            assert!(confidence("0.txt") < 0.1, "{calibration:?}: {results:?}");
+            assert!(results.iter().all(|(_, _, confidence)| (0.0..=1.0).contains(confidence)));
+        }
+    }
+
+    #[test]
+    fn excluded_documents_make_room_for_the_next_ones() {
+        let model = model_of(&[("a.txt", "rust rust rust"), ("b.txt", "rust rust other"), ("c.txt", "rust other other"), ("d.txt", "none")]);
+        let query = chars("rust");
+        let first = model.search_excluding(&query, &HashSet::new(), 1).unwrap();
+        assert_eq!(paths(&first), ["a.txt"]);
+        let second = model.search_excluding(&query, &HashSet::from([first[0].0.clone()]), 1).unwrap();
+        assert_eq!(second, model.search_query(&query).unwrap()[1..2]);
+        assert_eq!(paths(&second), ["b.txt"]);
+    }
+
+    #[test]
+    fn query_cost_is_the_sum_of_document_frequencies() {
+        let model = model_of(&[("a.txt", "common rare"), ("b.txt", "common"), ("c.txt", "common other")]);
+        let file = TempFile::new("estimate-cost.db");
+        let sqlite = SqliteModel::from_in_memory(&model, &file.0).unwrap();
+        for (query, cost) in [("rare", 1), ("common", 3), ("common rare", 4), ("missing", 0)] {
+            assert_eq!(model.estimate_cost(&chars(query)).unwrap(), cost, "{query}");
+            assert_eq!(sqlite.estimate_cost(&chars(query)).unwrap(), cost, "{query}");
+        }
+    }
+
+    #[test]
+    fn boosted_terms_outrank_equally_frequent_ones() {
+        let docs = [("error.txt", "error here"), ("warning.txt", "warning here"), ("other.txt", "nothing")];
+        let query = chars("error warning");
+        let plain = model_of(&docs).search_query(&query).unwrap();
+        assert_eq!(plain[0].1, plain[1].1);
+        assert_eq!(paths(&plain), ["error.txt", "warning.txt"]);
+
+        let model = model_of(&docs).with_term_boosts(HashMap::from([("error".to_string(), 2.0)]));
+        let boosted = model.search_query(&query).unwrap();
+        assert_eq!(paths(&boosted), ["error.txt", "warning.txt"]);
+        assert_eq!(boosted[0].1, 2.0 * boosted[1].1);
+        let model = model_of(&docs).with_term_boosts(HashMap::from([("warning".to_string(), 2.0)]));
+        assert_eq!(paths(&model.search_query(&query).unwrap()), ["warning.txt", "error.txt"]);
+    }
+
+    #[test]
+    fn partial_matches_are_near_misses() {
+        let model = model_of(&[("all.txt", "red green blue"), ("two.txt", "red green"), ("one.txt", "blue sky"), ("none.txt", "grey")]);
+        let (matches, near_misses) = model.search_with_near_misses(&chars("red green blue"), 3).unwrap();
+        assert_eq!(paths(&matches), ["all.txt"]);
+        assert_eq!(paths(&near_misses), ["two.txt", "one.txt"]);
+    }
+
+    #[test]
+    fn leading_byte_order_marks_are_ignored() {
+        let model = model_of(&[("bom.txt", "\u{feff}hello world"), ("other.txt", "goodbye")]);
+        assert_eq!(paths(&model.search_query(&chars("hello")).unwrap()), ["bom.txt"]);
+        assert!(!model.df.keys().any(|term| term.contains(BOM)));
+    }
+
+    #[test]
+    fn jaccard_ranks_by_term_set_overlap() {
+        let model = model_of(&[
+            ("superset.txt", "red green blue"),
+            ("exact.txt", "green red green"),
+            ("half.txt", "red yellow"),
+            ("none.txt", "purple"),
+        ]).with_scorer(Scorer::Jaccard);
+        let ranking = model.search_query(&chars("red green")).unwrap();
+        assert_eq!(paths(&ranking), ["exact.txt", "superset.txt", "half.txt"]);
+        assert_eq!(ranking.iter().map(|(_, score)| *score).collect::<Vec<_>>(), [1.0, 2.0 / 3.0, 1.0 / 3.0]);
+    }
+
+    #[test]
+    fn scanning_budget_makes_results_approximate() {
+        let corpus = deep_corpus(50);
+        let docs = corpus.iter().map(|(path, content)| (path.as_str(), content.as_str())).collect::<Vec<_>>();
+        let query = chars("report budget draft1");
+        let (ranking, approximate) = model_of(&docs).search_bounded(&query).unwrap();
+        assert_eq!((ranking.len(), approximate), (50, false));
+
+        let model = model_of(&docs).with_max_postings_scanned(Some(5));
+        let (ranking, approximate) = model.search_bounded(&query).unwrap();
+        assert!(approximate);
+        assert!(!ranking.is_empty() && ranking.len() <= 5, "{ranking:?}");
+        // The rarest term is scanned first.
+        assert!(ranking.iter().all(|(path, _)| model.doc(path).unwrap().tf.contains_key("DRAFT1")));
+    }
+
+    #[test]
+    fn configuration_is_saved_with_the_index() {
+        let mut model = InMemoryModel::default().with_preset(Preset::Prose);
+        for (path, content) in [("a.txt", "The indexes of the engine"), ("b.txt", "Indexing is running"), ("c.txt", "a walk in the park")] {
+            model.add_document(PathBuf::from(path), &chars(content)).unwrap();
+        }
+        let file = TempFile::new("saved-config.json");
+        fs::write(&file.0, serde_json::to_string(&model).unwrap()).unwrap();
+        let loaded = InMemoryModel::load(&file.0, None).unwrap();
+        assert_eq!(loaded.config(), model.config());
+        for query in ["index", "the indexed engines", "runs", "the"] {
+            assert_eq!(loaded.search_query(&chars(query)).unwrap(), model.search_query(&chars(query)).unwrap(), "{query}");
+        }
+        assert_eq!(paths(&loaded.search_query(&chars("indexed")).unwrap()), ["a.txt", "b.txt"]);
+
+        assert!(InMemoryModel::load(&file.0, Some(model.config())).is_ok());
+        assert!(InMemoryModel::load(&file.0, Some(InMemoryModel::default().config())).is_err());
+    }
+
+    #[test]
+    fn top_k_of_sqlite_matches_in_memory() {
+        let file = TempFile::new("top-k.db");
+        let sqlite = sqlite_corpus(&file);
+        let model = InMemoryModel::from_sqlite(&sqlite).unwrap();
+        for query in ["text7 rust", "text7 text8 text9 filler70", "rust"] {
+            let all = model.search_query(&chars(query)).unwrap();
+            assert!(all.len() >= 5, "{query}");
+            for k in [0, 1, 5, 12] {
+                let top = sqlite.search_top_k(&chars(query), k).unwrap();
+                let expected = &all[..k.min(all.len())];
+                assert_eq!(paths(&top), paths(expected), "{query} {k}");
+                for ((_, score), (_, expected)) in top.iter().zip(expected) {
+                    assert!((score - expected).abs() < 1e-6, "{query} {k}: {score} != {expected}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn top_k_never_holds_more_than_k() {
+        let mut top = TopK::new(3);
+        for i in 0..100 {
+            top.push(PathBuf::from(format!("{i}.txt")), (i % 7) as f32);
+            assert!(top.heap.len() <= 3);
+        }
+        assert_eq!(paths(&top.into_ranking()), ["13.txt", "20.txt", "27.txt"]);
+    }
+
+    #[test]
+    fn rerank_puts_the_most_relevant_candidate_first() {
+        let candidates = ["the cat sat", "rust compiler errors in rust", "rust is fast"].map(chars);
+        let query = chars("rust compiler");
+        let ranking = rerank(&query, &candidates, None);
+        assert_eq!(ranking.iter().map(|(i, _)| *i).collect::<Vec<_>>(), [1, 2, 0]);
+        assert_eq!(ranking[2].1, 0.0);
+
+        // Counted over a large corpus where "compiler" is everywhere.
+        let df = DocFreq::from([("RUST".to_string(), 10), ("COMPILER".to_string(), 1000)]);
+        let ranking = rerank(&query, &candidates, Some((&df, 1000)));
+        assert_eq!(ranking.iter().map(|(i, _)| *i).collect::<Vec<_>>(), [1, 2, 0]);
+        assert_eq!(ranking[0].1, 2.0 / 5.0 * 2.0);
+        assert_eq!(ranking[1].1, 1.0 / 3.0 * 2.0);
+    }
+
+    #[test]
+    fn bulk_removal_equals_one_by_one_removal() {
+        let corpus = deep_corpus(100);
+        let removed = corpus.iter().step_by(2).map(|(path, _)| PathBuf::from(path)).collect::<Vec<_>>();
+        let queries = ["budget q3 draft1", "item7 item8", "report"];
+        let df_of = |model: &dyn Model| {
+            let mut df = model.most_frequent_terms(usize::MAX).unwrap();
+            df.sort();
+            df
+        };
+
+        let mut bulk = InMemoryModel::default();
+        let mut single = InMemoryModel::default();
+        let (file_bulk, file_single) = (TempFile::new("remove-bulk.db"), TempFile::new("remove-single.db"));
+        let mut sqlite_bulk = SqliteModel::open(&file_bulk.0).unwrap();
+        let mut sqlite_single = SqliteModel::open(&file_single.0).unwrap();
+        for model in [&mut bulk as &mut dyn Model, &mut single, &mut sqlite_bulk, &mut sqlite_single] {
+            for (path, content) in &corpus {
+                model.add_document(PathBuf::from(path), &chars(content)).unwrap();
+            }
+        }
+
+        assert_eq!(bulk.remove_documents(&removed).unwrap(), 50);
+        assert_eq!(sqlite_bulk.remove_documents(&removed).unwrap(), 50);
+        for path in &removed {
+            assert!(single.remove_document(path).unwrap());
+            assert!(sqlite_single.remove_document(path).unwrap());
+        }
+        assert_eq!(bulk.remove_documents(&removed).unwrap(), 0);
+        assert_eq!(sqlite_bulk.remove_documents(&removed).unwrap(), 0);
+
+        assert_eq!(bulk.df, single.df);
+        assert_eq!(df_of(&sqlite_bulk), df_of(&sqlite_single));
+        assert_eq!(df_of(&sqlite_bulk), df_of(&bulk));
+        // Terms found only in removed documents are gone from the document frequencies.
+        assert!(!bulk.df.contains_key("ITEM0") && bulk.df.contains_key("ITEM1"));
+        for query in queries {
+            let expected = single.search_query(&chars(query)).unwrap();
+            assert_eq!(bulk.search_query(&chars(query)).unwrap(), expected, "{query}");
+            assert_eq!(sqlite_bulk.search_query(&chars(query)).unwrap(), sqlite_single.search_query(&chars(query)).unwrap(), "{query}");
+            assert_eq!(paths(&sqlite_bulk.search_query(&chars(query)).unwrap()), paths(&expected), "{query}");
+        }
+        assert_eq!(sqlite_bulk.document_count().unwrap(), 50);
+    }
+
+    #[test]
+    fn searching_an_empty_index_finds_nothing() {
+        let file = TempFile::new("empty.db");
+        let sqlite = SqliteModel::open(&file.0).unwrap();
+        let model = InMemoryModel::default();
+        let with_zero_scores = InMemoryModel::default().with_zero_scores(true);
+        // Emptied by removing its only document rather than never filled.
+        let mut emptied = model_of(&[("a.txt", "rust")]).with_zero_scores(true);
+        emptied.remove_document(Path::new("a.txt")).unwrap();
+        for query in ["rust", "", "a b c"] {
+            assert_eq!(model.search_query(&chars(query)).unwrap(), []);
+            assert_eq!(sqlite.search_query(&chars(query)).unwrap(), []);
+            assert_eq!(with_zero_scores.search_query(&chars(query)).unwrap(), []);
+            assert_eq!(emptied.search_query(&chars(query)).unwrap(), []);
+            assert!(model.search_with_confidence(&chars(query)).unwrap().is_empty());
+        }
+        assert_eq!(model.document_count().unwrap(), 0);
+        assert_eq!(sqlite.document_count().unwrap(), 0);
+        assert_eq!(compute_idf("RUST", 0, &DocFreq::new()), 0.0);
+    }
+
+    #[test]
+    fn directories_are_indexed_in_one_transaction() {
+        let dir = TempFile::new("index-directory");
+        fs::create_dir_all(dir.0.join("nested")).unwrap();
+        fs::write(dir.0.join("rust.txt"), "rust search engine").unwrap();
+        fs::write(dir.0.join("nested/python.md"), "python search").unwrap();
+        fs::write(dir.0.join("nested/notes.xml"), "<notes>rust notes</notes>").unwrap();
+        fs::write(dir.0.join("broken.xml"), "<notes>unclosed").unwrap();
+        fs::write(dir.0.join("image.png"), [0x89, 0x50, 0x4e, 0x47]).unwrap();
+
+        let file = TempFile::new("index-directory.db");
+        let mut model = SqliteModel::open(&file.0).unwrap();
+        assert_eq!(model.index_directory(&dir.0, &IndexOptions::default()).unwrap(), 3);
+        assert!(model.index_directory(&dir.0.join("missing"), &IndexOptions::default()).is_err());
+        drop(model);
+
+        let model = SqliteModel::open(&file.0).unwrap();
+        assert_eq!(model.document_count().unwrap(), 3);
+        let ranking = model.search_query(&chars("rust")).unwrap();
+        assert_eq!(ranking.len(), 2);
+        assert!(ranking.iter().all(|(path, _)| path.ends_with("rust.txt") || path.ends_with("notes.xml")));
+        assert!(model.search_query(&chars("unclosed")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn decay_favours_early_occurrences() {
+        let docs = [("late.txt", "a b c d e f g rust"), ("early.txt", "rust a b c d e f g"), ("other.txt", "x")];
+        let plain = model_of(&docs).search_query(&chars("rust")).unwrap();
+        assert_eq!(plain[0].1, plain[1].1);
+
+        let model = model_of(&docs).with_position_weighting(PositionWeighting::Decay { half_life: 2 });
+        let ranking = model.search_query(&chars("rust")).unwrap();
+        assert_eq!(paths(&ranking), ["early.txt", "late.txt"]);
+        assert!(ranking[0].1 > ranking[1].1);
+        assert_eq!(ranking[0].1, plain[0].1);
+    }
+
+    #[test]
+    fn preview_matches_what_is_indexed() {
+        let content = chars("The indexes of the engine were indexed by js and js scripts");
+        let mut model = InMemoryModel::default().with_preset(Preset::Prose).with_max_document_terms(Some(5));
+        model.add_document(PathBuf::from("other.txt"), &chars("javascript")).unwrap();
+        model.merge_terms("js", "javascript").unwrap();
+
+        let preview = model.preview_document(&content);
+        assert!(preview.dropped.contains(&("THE".to_string(), DropReason::StopWord)));
+        assert!(preview.stemmed.contains(&("INDEXES".to_string(), "INDEX".to_string())));
+        assert_eq!(preview.tf.get("JAVASCRIPT"), Some(&2));
+        assert_eq!(preview.tf.get("JS"), None);
+        assert_eq!(preview.tf.get("SCRIPT"), None);
+
+        model.add_document(PathBuf::from("doc.txt"), &content).unwrap();
+        let doc = model.doc(Path::new("doc.txt")).unwrap();
+        assert_eq!((&preview.tf, preview.count), (&doc.tf, doc.count));
+        assert_eq!(preview.count, 5);
+    }
+
+    #[test]
+    fn nearest_documents_are_from_the_same_cluster() {
+        let model = model_of(&[
+            ("rust1.txt", "rust borrow checker lifetimes"),
+            ("rust2.txt", "rust lifetimes borrow traits"),
+            ("rust3.txt", "rust traits checker"),
+            ("cook1.txt", "bake bread flour oven checker"),
+            ("cook2.txt", "bread oven flour"),
+            ("cook3.txt", "soup"),
+        ]);
+        let nearest = model.nearest_documents(Path::new("rust1.txt"), 10).unwrap();
+        assert_eq!(paths(&nearest), ["rust2.txt", "rust3.txt", "cook1.txt"]);
+        assert!(nearest[1].1 > nearest[2].1);
+        assert_eq!(paths(&model.nearest_documents(Path::new("cook2.txt"), 1).unwrap()), ["cook1.txt"]);
+        assert!(model.nearest_documents(Path::new("missing.txt"), 1).is_err());
+    }
+
+    #[test]
+    fn term_frequencies_match_the_indexed_ones() {
+        let content = chars("Rust, rust and more RUST: 42 crates.");
+        let (tf, count) = term_frequencies(&content);
+        assert_eq!((tf["RUST"], count), (3, 10));
+
+        let model = model_of(&[("a.txt", "Rust, rust and more RUST: 42 crates.")]);
+        let doc = model.doc(Path::new("a.txt")).unwrap();
+        assert_eq!((&tf, count), (&doc.tf, doc.count));
+    }
+
+    #[test]
+    fn short_query_terms_are_dropped_or_expanded() {
+        let docs = [("apple.txt", "apple pie"), ("avocado.txt", "avocado toast"), ("banana.txt", "banana split")];
+        let model = model_of(&docs).with_min_query_term_len(2, ShortTermPolicy::Drop);
+        assert!(model.search_query(&chars("a")).unwrap().is_empty());
+        assert_eq!(paths(&model.search_query(&chars("a banana")).unwrap()), ["banana.txt"]);
+
+        let model = model_of(&docs).with_min_query_term_len(2, ShortTermPolicy::ExpandPrefix);
+        assert_eq!(paths(&model.search_query(&chars("a")).unwrap()), ["apple.txt", "avocado.txt"]);
+        assert_eq!(model.search_query(&chars("a")).unwrap(), model_of(&docs).search_query(&chars("apple avocado")).unwrap());
+    }
+
+    #[test]
+    fn indexes_convert_between_backends() {
+        let corpus = deep_corpus(30);
+        let docs = corpus.iter().map(|(path, content)| (path.as_str(), content.as_str())).collect::<Vec<_>>();
+        let model = model_of(&docs);
+        let file = TempFile::new("convert.db");
+        let sqlite = SqliteModel::from_in_memory(&model, &file.0).unwrap();
+        let back = InMemoryModel::from_sqlite(&sqlite).unwrap();
+        assert_eq!(back.df, model.df);
+        for query in ["budget q3 draft1", "item7", "report budget", "missing"] {
+            let expected = model.search_query(&chars(query)).unwrap();
+            let converted = sqlite.search_query(&chars(query)).unwrap();
+            assert_eq!(paths(&converted), paths(&expected), "{query}");
+            for ((_, score), (_, expected)) in converted.iter().zip(&expected) {
+                assert!((score - expected).abs() < 1e-6, "{query}: {score} != {expected}");
+            }
+            assert_eq!(back.search_query(&chars(query)).unwrap(), expected, "{query}");
+        }
+
+        let file = TempFile::new("convert-config.db");
+        assert!(SqliteModel::from_in_memory(&model_of(&docs).with_preset(Preset::Prose), &file.0).is_err());
+        assert!(SqliteModel::from_in_memory(&model_of(&docs).with_scorer(Scorer::Jaccard), &file.0).is_err());
+    }
+
+    #[test]
+    fn ascii_folding_matches_words_without_their_accents() {
+        let docs = [("cv.txt", "café résumé"), ("other.txt", "cafeteria")];
+        assert!(model_of(&docs).search_query(&chars("cafe resume")).unwrap().is_empty());
+
+        let mut model = InMemoryModel::default().with_lexer(LexerConfig { ascii_fold: true, ..LexerConfig::default() });
+        for (path, content) in docs {
+            model.add_document(PathBuf::from(path), &chars(content)).unwrap();
+        }
+        assert_eq!(paths(&model.search_query(&chars("cafe resume")).unwrap()), ["cv.txt"]);
+        assert_eq!(model.search_query(&chars("CAFÉ")).unwrap(), model.search_query(&chars("cafe")).unwrap());
+    }
+
+    #[test]
+    fn most_frequent_terms_come_first() {
+        let model = model_of(&[("a.txt", "log error"), ("b.txt", "log warning warning warning"), ("c.txt", "log error"), ("d.txt", "info")]);
+        let file = TempFile::new("frequent-terms.db");
+        let sqlite = SqliteModel::from_in_memory(&model, &file.0).unwrap();
+        for backend in [&model as &dyn Model, &sqlite] {
+            assert_eq!(backend.most_frequent_terms(1).unwrap(), [("LOG".to_string(), 3)]);
+            assert_eq!(backend.most_frequent_terms(3).unwrap(),
+                       [("LOG".to_string(), 3), ("ERROR".to_string(), 2), ("INFO".to_string(), 1)]);
+            assert_eq!(backend.most_frequent_terms(10).unwrap().len(), 4);
+        }
+    }
+
+    #[test]
+    fn lead_weighting_favours_mentions_in_the_opening() {
+        let docs = [("end.txt", "a b c d e f g rust"), ("lead.txt", "rust a b c d e f g"), ("other.txt", "x")];
+        assert_eq!(paths(&model_of(&docs).search_query(&chars("rust")).unwrap()), ["end.txt", "lead.txt"]);
+
+        let model = model_of(&docs).with_position_weighting(PositionWeighting::Lead { positions: 3, boost: 2.0 });
+        let ranking = model.search_query(&chars("rust")).unwrap();
+        assert_eq!(paths(&ranking), ["lead.txt", "end.txt"]);
+        assert_eq!(ranking[0].1, 2.0 * ranking[1].1);
+    }
+
+    #[test]
+    fn ties_at_the_cutoff_are_broken_by_path() {
+        let tied = (0..10).map(|i| (format!("{i}.txt"), "tie".to_string())).collect::<Vec<_>>();
+        for order in [tied.clone(), tied.iter().rev().cloned().collect()] {
+            let mut docs = order.iter().map(|(path, content)| (path.as_str(), content.as_str())).collect::<Vec<_>>();
+            docs.push(("other.txt", "other"));
+            let model = model_of(&docs);
+            let top = model.score_topic(&[chars("tie")], TopicCombine::Max, 3).unwrap();
+            assert_eq!(paths(&top), ["0.txt", "1.txt", "2.txt"]);
+
+            let file = TempFile::new("ties.db");
+            let sqlite = SqliteModel::from_in_memory(&model, &file.0).unwrap();
+            assert_eq!(sqlite.search_top_k(&chars("tie"), 3).unwrap(), top);
+        }
+    }
+
+    #[test]
+    fn field_fusion_strategies_rank_differently() {
+        let fused = |fusion| {
+            let mut model = InMemoryModel::default().with_field_fusion(fusion, HashMap::new());
+            for (path, title, body) in [("title.txt", "rust", "a b c"), ("body.txt", "rust a", "rust rust a"), ("other.txt", "other", "text")] {
+                model.add_document_with_fields(PathBuf::from(path), &[("title", &chars(title)), ("body", &chars(body))]).unwrap();
+            }
+            model.search_query(&chars("rust")).unwrap()
+        };
+        let idf = 1.5f32.log10();
+
+        let sum = fused(FieldFusion::WeightedSum);
+        assert_eq!(paths(&sum), ["body.txt", "title.txt"]);
+        assert!((sum[0].1 - (0.5 + 2.0 / 3.0) * idf).abs() < 1e-6);
+        let max = fused(FieldFusion::Max);
+        assert_eq!(paths(&max), ["title.txt", "body.txt"]);
+        assert_eq!(max[0].1, idf);
+
+        // First in the title ranking and absent from the body one, against
+        // second in the title ranking and first in the body one.
+        let rrf = fused(FieldFusion::ReciprocalRank);
+        assert_eq!(paths(&rrf), ["body.txt", "title.txt"]);
+        assert_eq!(rrf[0].1, 1.0 / (RRF_K + 2.0) + 1.0 / (RRF_K + 1.0));
+        assert_eq!(rrf[1].1, 1.0 / (RRF_K + 1.0));
+    }
+
+    #[test]
+    fn rounded_scores_rank_the_same_in_parallel() {
+        let long = format!("{} {}", "apple ".repeat(333), "filler ".repeat(667));
+        let mut docs = vec![("a.txt", long.as_str()), ("b.txt", "apple pear plum"), ("c.txt", "other"), ("d.txt", "more")];
+        let corpus = deep_corpus(40);
+        docs.extend(corpus.iter().map(|(path, content)| (path.as_str(), content.as_str())));
+        let query = chars("apple budget draft2 item7");
+
+        // Less than the rounding apart, 333/1000 against 1/3 of the same IDF.
+        let exact = model_of(&docs).search_query(&query).unwrap();
+        assert_eq!(paths(&exact[..2]), ["b.txt", "a.txt"]);
+        assert_ne!(exact[0].1, exact[1].1);
+
+        let model = model_of(&docs).with_score_rounding(Some(3));
+        let serial = model.search_query(&query).unwrap();
+        assert_eq!(paths(&serial[..2]), ["a.txt", "b.txt"]);
+        assert_eq!(serial[0].1, serial[1].1);
+        for threads in 1..=5 {
+            assert_eq!(model.search_parallel(&query, threads).unwrap(), serial, "{threads} threads");
+        }
+    }
+
+    #[test]
+    fn phrases_match_across_dropped_stop_words() {
+        let mut model = InMemoryModel::default().with_lexer(LexerConfig { stop_words: true, ..LexerConfig::default() });
+        for (path, content) in [("king.txt", "the king of england"), ("queen.txt", "king england queen"), ("other.txt", "england of king")] {
+            model.add_document(PathBuf::from(path), &chars(content)).unwrap();
+        }
+        assert_eq!(model.doc(Path::new("king.txt")).unwrap().positions["ENGLAND"], [3]);
+        assert_eq!(model.phrase_document_frequency(&chars("king of england")), 1);
+        assert_eq!(paths(&model.search_boolean(&Query::phrase("king of england")).unwrap()), ["king.txt"]);
+        // Any stop word fills the gap, but it has to be there.
+        assert_eq!(model.phrase_document_frequency(&chars("king the england")), 1);
+        assert_eq!(paths(&model.search_boolean(&Query::phrase("king england")).unwrap()), ["queen.txt"]);
+    }
+
+    #[test]
+    fn only_extracted_json_fields_are_indexed() {
+        let mut model = InMemoryModel::default();
+        let json = r#"{"title": "Rust search", "user": {"name": "ferris", "id": 7}, "secret": "password", "tags": ["fast", "safe"]}"#;
+        model.add_json_document(PathBuf::from("a.json"), json, &["/title", "/user", "/tags", "/missing"]).unwrap();
+        model.add_json_document(PathBuf::from("b.json"), r#"{"title": "Python", "tags": ["rust"]}"#, &["/title", "/tags"]).unwrap();
+
+        assert_eq!(paths(&model.search_field(&chars("rust"), "title").unwrap()), ["a.json"]);
+        assert_eq!(paths(&model.search_field(&chars("ferris 7"), "user").unwrap()), ["a.json"]);
+        assert_eq!(paths(&model.search_field(&chars("rust"), "tags").unwrap()), ["b.json"]);
+        assert!(model.search_query(&chars("password")).unwrap().is_empty());
+        assert!(!model.df.contains_key("PASSWORD") && !model.df.contains_key("SECRET"));
+        assert!(model.add_json_document(PathBuf::from("c.json"), "{not json", &["/title"]).is_err());
+    }
+
+    #[test]
+    fn explanations_add_up_to_the_score() {
+        let model = model_of(&[("a.txt", "rust search engine rust"), ("b.txt", "rust python"), ("c.txt", "engine oil"), ("d.txt", "x")]);
+        let query = chars("rust engine missing");
+        let json: serde_json::Value = serde_json::from_str(&model.explain_json(&query, Path::new("a.txt")).unwrap()).unwrap();
+        assert_eq!(json["path"], "a.txt");
+        let terms = json["terms"].as_array().unwrap();
+        assert_eq!(terms.iter().map(|term| term["term"].as_str().unwrap()).collect::<Vec<_>>(), ["RUST", "ENGINE", "MISSING"]);
+        let rust = &terms[0];
+        assert_eq!(rust["tf"].as_f64().unwrap() as f32, 0.5);
+        assert_eq!(rust["idf"].as_f64().unwrap() as f32, 2f32.log10());
+        assert_eq!(rust["contribution"].as_f64().unwrap() as f32, 0.5 * 2f32.log10());
+        assert_eq!(terms[2]["contribution"].as_f64().unwrap(), 0.0);
+
+        let sum = terms.iter().map(|term| term["contribution"].as_f64().unwrap() as f32).sum::<f32>();
+        let score = model.search_query(&query).unwrap().into_iter().find(|(path, _)| path == Path::new("a.txt")).unwrap().1;
+        assert!((sum - score).abs() < 1e-6, "{sum} != {score}");
+        assert_eq!(json["score"].as_f64().unwrap() as f32, score);
+
+        assert!(model.explain_json(&query, Path::new("missing.txt")).is_err());
+        assert!(model_of(&[("a.txt", "rust")]).with_scorer(Scorer::Jaccard).explain(&query, Path::new("a.txt")).is_err());
+    }
+
+    #[test]
+    fn stale_idfs_are_refreshed_past_the_threshold() {
+        let fraction = 0.5;
+        let mut model = InMemoryModel::default().with_idf_staleness(fraction);
+        let mut counts = Vec::new();
+        for i in 0..12 {
+            let content = if i == 0 { "apple x".to_string() } else { format!("filler{i} x") };
+            model.add_document(PathBuf::from(format!("{i:02}.txt")), &chars(&content)).unwrap();
+            counts.push(model.idf_document_count());
+
+            // "apple" is in the first document only, so only the number of
+            // documents it is counted over goes stale.
+            let stale = model.explain(&chars("apple"), Path::new("00.txt")).unwrap().terms[0].idf;
+            let exact = ((i + 1) as f32).log10();
+            assert!((stale - exact).abs() <= (1.0 + fraction).log10() + 1e-6, "{i}: {stale} != {exact}");
+        }
+        assert_eq!(counts, [1, 2, 2, 4, 4, 4, 7, 7, 7, 7, 11, 11]);
+
+        model.remove_documents(&(6..12).map(|i| PathBuf::from(format!("{i:02}.txt"))).collect::<Vec<_>>()).unwrap();
+        assert_eq!(model.idf_document_count(), 11);
+        model.remove_document(Path::new("05.txt")).unwrap();
+        assert_eq!(model.idf_document_count(), 5);
+
+        let loaded = save_and_load(&model, "stale-idf.json");
+        assert_eq!(loaded.idf_document_count(), 5);
+        let query = chars("apple");
+        assert!(loaded.search_query(&query).unwrap()[0].1 > 0.0);
+        assert_eq!(loaded.search_query(&query).unwrap(), model.search_query(&query).unwrap());
+    }
+
+    #[test]
+    fn normalized_tokens_search_like_the_raw_query() {
+        let mut model = InMemoryModel::default().with_preset(Preset::Prose);
+        for (path, content) in [("a.txt", "Indexing the documents"), ("b.txt", "indexed pages"), ("c.txt", "other documents")] {
+            model.add_document(PathBuf::from(path), &chars(content)).unwrap();
+        }
+        let query = chars("The indexes of documents");
+        let tokens = model.lexer(&query).collect::<Vec<_>>();
+        assert_eq!(tokens, ["INDEX", "DOCUMENT"]);
+        assert_eq!(model.search_tokens(&tokens).unwrap(), model.search_query(&query).unwrap());
+        // Tokens are taken as they are.
+        assert!(model.search_tokens(&["indexes".to_string()]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn long_documents_are_truncated() {
+        let mut model = InMemoryModel::default().with_max_document_terms(Some(4));
+        model.add_document(PathBuf::from("long.txt"), &chars("one two three four five six")).unwrap();
+        model.add_document(PathBuf::from("short.txt"), &chars("one two")).unwrap();
+        model.add_document_with_fields(PathBuf::from("fields.txt"), &[
+            ("title", &chars("alpha beta gamma")),
+            ("body", &chars("delta epsilon zeta")),
+        ]).unwrap();
+
+        let long = model.doc(Path::new("long.txt")).unwrap();
+        assert_eq!(long.count, 4);
+        assert_eq!(long.tf.values().sum::<usize>(), 4);
+        assert_eq!(model.is_truncated(Path::new("long.txt")), Some(true));
+        assert_eq!(model.is_truncated(Path::new("short.txt")), Some(false));
+        assert_eq!(model.is_truncated(Path::new("missing.txt")), None);
+        assert_eq!(paths(&model.search_query(&chars("four")).unwrap()), ["long.txt"]);
+        assert!(model.search_query(&chars("five six")).unwrap().is_empty());
+
+        // The title takes 3 of the 4 terms, leaving 1 to the body.
+        let fields = model.doc(Path::new("fields.txt")).unwrap();
+        assert_eq!((fields.count, fields.fields["title"].count, fields.fields["body"].count), (4, 3, 1));
+        assert_eq!(model.is_truncated(Path::new("fields.txt")), Some(true));
+        assert_eq!(paths(&model.search_field(&chars("delta"), "body").unwrap()), ["fields.txt"]);
+        assert!(model.search_query(&chars("epsilon zeta")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn common_query_terms_are_ignored_below_the_minimum_idf() {
+        let docs = [
+            ("common.txt", "common common common"),
+            ("rare.txt", "rare f f f f f f f"),
+            ("b.txt", "common b"),
+            ("c.txt", "common c"),
+        ];
+        let query = chars("common rare");
+        assert_eq!(paths(&model_of(&docs).search_query(&query).unwrap())[0], "common.txt");
+
+        let model = model_of(&docs).with_min_query_idf(Some(0.3));
+        assert_eq!(paths(&model.search_query(&query).unwrap()), ["rare.txt"]);
+        // With no term above the minimum, all of them are searched.
+        assert_eq!(model.search_query(&chars("common")).unwrap(), model_of(&docs).search_query(&chars("common")).unwrap());
+    }
+
+    #[test]
+    fn cursor_pages_add_up_to_the_ranking() {
+        let file = TempFile::new("cursor.db");
+        let model = sqlite_corpus(&file);
+        for query in ["text7 rust", "text7 text7 filler3", "rust", "missing", ""] {
+            let expected = model.search_query(&chars(query)).unwrap();
+            for page_size in [1, 3, 7, 1000] {
+                let mut cursor = model.search_cursor(&chars(query)).unwrap();
+                let mut pages = Vec::new();
+                loop {
+                    let page = cursor.next_page(page_size).unwrap();
+                    assert!(page.len() <= page_size);
+                    if page.is_empty() {
+                        break
+                    }
+                    pages.extend(page);
+                }
+                assert!(cursor.next_page(page_size).unwrap().is_empty());
+                // Tied documents come in the same order, by path.
+                assert_eq!(paths(&pages), paths(&expected), "{query} by {page_size}");
+                for ((_, score), (_, expected)) in pages.iter().zip(&expected) {
+                    assert!((score - expected).abs() < 1e-6, "{query}: {score} != {expected}");
+                }
+            }
+        }
+        assert_eq!(model.search_query(&chars("text7")).unwrap().len(), 10);
+    }
+
+    #[test]
+    fn near_duplicates_are_grouped() {
+        let model = model_of(&[
+            ("report.txt", "quarterly revenue grew in the north region"),
+            ("report-copy.txt", "quarterly revenue grew in the north region"),
+            ("report-edit.txt", "quarterly revenue grew in the north region revenue"),
+            ("recipe.txt", "bake the bread in a hot oven"),
+            ("recipe-copy.txt", "bake the bread in a hot oven"),
+            ("poem.txt", "the moon over the north sea"),
+        ]);
+        assert_eq!(model.find_near_duplicates(0.9), [
+            vec![PathBuf::from("recipe-copy.txt"), PathBuf::from("recipe.txt")],
+            vec![PathBuf::from("report-copy.txt"), PathBuf::from("report-edit.txt"), PathBuf::from("report.txt")],
+        ]);
+        assert!(model.find_near_duplicates(1.1).is_empty());
+    }
+
+    #[test]
+    fn newer_format_versions_are_refused() {
+        let model = model_of(&[("a.txt", "alpha"), ("b.txt", "beta")]);
+        let mut json: serde_json::Value = serde_json::to_value(&model).unwrap();
+        assert_eq!(json["format_version"], FORMAT_VERSION);
+        let file = TempFile::new("format-version.json");
+
+        // Everything else parses.
+        json["format_version"] = (FORMAT_VERSION + 1).into();
+        fs::write(&file.0, json.to_string()).unwrap();
+        assert!(InMemoryModel::load(&file.0, None).is_err());
+
+        // The newer format changed the type of a known field.
+        json["df"] = "a format the crate does not know".into();
+        fs::write(&file.0, json.to_string()).unwrap();
+        assert!(InMemoryModel::load(&file.0, None).is_err());
+
+        // Unknown fields of the current version are ignored.
+        let mut json = serde_json::to_value(&model).unwrap();
+        json["added_later"] = true.into();
+        fs::write(&file.0, json.to_string()).unwrap();
+        assert_eq!(InMemoryModel::load(&file.0, None).unwrap().search_query(&chars("alpha")).unwrap(),
+                   model.search_query(&chars("alpha")).unwrap());
+    }
+
+    #[test]
+    fn expanded_queries_are_held_to_the_term_cap() {
+        let docs = (0..50).map(|i| (format!("{i}.txt"), format!("a{i} filler"))).collect::<Vec<_>>();
+        let docs = docs.iter().map(|(path, content)| (path.as_str(), content.as_str())).collect::<Vec<_>>();
+        let expanding = || model_of(&docs).with_min_query_term_len(2, ShortTermPolicy::ExpandPrefix);
+        assert_eq!(expanding().search_query(&chars("a")).unwrap().len(), 50);
+
+        let model = expanding().with_max_query_terms(1, QueryTermOverflow::Error);
+        assert!(model.search_query(&chars("a")).is_err());
+        assert!(model.estimate_cost(&chars("a")).is_err());
+        let model = expanding().with_max_query_terms(3, QueryTermOverflow::Truncate);
+        // The first terms in alphabetical order.
+        assert_eq!(paths(&model.search_query(&chars("a")).unwrap()), ["0.txt", "1.txt", "10.txt"]);
+
+        let substrings = || model_of(&docs).with_substring_fallback(true);
+        assert_eq!(substrings().search_query(&chars("a")).unwrap().len(), 50);
+        assert!(substrings().with_max_query_terms(1, QueryTermOverflow::Error).search_query(&chars("a")).is_err());
+        let model = substrings().with_max_query_terms(2, QueryTermOverflow::Truncate);
+        assert_eq!(paths(&model.search_query(&chars("a")).unwrap()), ["0.txt", "1.txt"]);
+    }
+
+    #[test]
+    fn debug_results_carry_the_lexer_counts() {
+        let docs = [("a.txt", "Rust rust borrow checker, rust again"), ("b.txt", "borrow the book, return the book")];
+        let query = chars("rust borrow book missing");
+        let model = model_of(&docs);
+        assert!(model.search_results(&query).unwrap().iter().all(|result| result.debug.is_none()));
+
+        let model = model_of(&docs).with_debug_results(true);
+        let results = model.search_results(&query).unwrap();
+        assert_eq!(results.len(), 2);
+        for result in results {
+            let content = docs.iter().find(|(path, _)| result.path == Path::new(path)).unwrap().1;
+            let (tf, count) = term_frequencies(&chars(content));
+            let debug = result.debug.unwrap();
+            let expected = Lexer::new(&query)
+                .filter_map(|term| Some((term.clone(), *tf.get(&term)?)))
+                .collect::<HashMap<_, _>>();
+            assert_eq!(debug.term_frequencies, expected);
+            assert_eq!(debug.term_count, count);
+            assert_eq!(debug.term_count, result.term_count);
+        }
+        let debug = |path: &str| model.search_results(&query).unwrap().into_iter().find(|result| result.path == Path::new(path)).unwrap().debug.unwrap();
+        assert_eq!(debug("a.txt").term_frequencies["RUST"], 3);
+        assert_eq!(debug("b.txt").term_frequencies["BOOK"], 2);
+    }
+
+    #[test]
+    fn bytes_are_indexed_around_a_bom_and_invalid_utf8() {
+        let mut model = InMemoryModel::default();
+        model.add_document_bytes(PathBuf::from("a.txt"), b"\xEF\xBB\xBFfirst words \xC3\x28 broken\xFFbytes last").unwrap();
+        model.add_document(PathBuf::from("b.txt"), &chars("unrelated")).unwrap();
+        for word in ["first", "words", "broken", "bytes", "last"] {
+            assert_eq!(paths(&model.search_query(&chars(word)).unwrap()), ["a.txt"], "{word}");
+        }
+        // Nothing is glued to the byte order mark.
+        assert!(model.doc(Path::new("a.txt")).unwrap().tf.keys().all(|term| !term.contains(BOM)));
+    }
+
+    #[test]
+    fn topic_scores_combine_the_query_scores() {
+        let model = model_of(&[
+            ("rust.txt", "rust rust notes"),
+            ("python.txt", "python notes notes"),
+            ("both.txt", "rust python"),
+            ("other.txt", "gardening"),
+        ]);
+        let queries = [chars("rust"), chars("python")];
+        let scores = |query: &[char]| model.search_query(query).unwrap().into_iter().collect::<HashMap<_, _>>();
+        let (rust, python) = (scores(&queries[0]), scores(&queries[1]));
+        let score = |scores: &HashMap<PathBuf, f32>, path: &str| scores.get(Path::new(path)).copied().unwrap_or(0.0);
+
+        let max = model.score_topic(&queries, TopicCombine::Max, 10).unwrap();
+        assert_eq!(paths(&max), ["rust.txt", "both.txt", "python.txt"]);
+        for (path, topic) in &max {
+            let path = path.to_str().unwrap();
+            assert_eq!(*topic, score(&rust, path).max(score(&python, path)));
+        }
+
+        let mean = model.score_topic(&queries, TopicCombine::Mean, 10).unwrap();
+        assert_eq!(paths(&mean), ["both.txt", "rust.txt", "python.txt"]);
+        for (path, topic) in &mean {
+            let path = path.to_str().unwrap();
+            assert_eq!(*topic, (score(&rust, path) + score(&python, path)) / 2.0);
+        }
+        assert_eq!(paths(&model.score_topic(&queries, TopicCombine::Mean, 1).unwrap()), ["both.txt"]);
+    }
+
+    #[test]
+    fn exported_vectors_share_dimensions() {
+        let model = model_of(&[("a.txt", "rust search engine"), ("b.txt", "rust index engine"), ("c.txt", "python")]);
+        let space = model.vector_space();
+        assert_eq!(space.len(), 5);
+        let (a, b) = (space.sparse_vector(Path::new("a.txt")).unwrap(), space.sparse_vector(Path::new("b.txt")).unwrap());
+        let dimensions = |vector: &[(u32, f32)]| vector.iter().map(|(dimension, _)| *dimension).collect::<HashSet<_>>();
+        let shared = dimensions(&a).intersection(&dimensions(&b)).copied().collect::<HashSet<_>>();
+        assert_eq!(shared, HashSet::from([space.dimension("RUST").unwrap(), space.dimension("ENGINE").unwrap()]));
+
+        for (path, vector) in [("a.txt", &a), ("b.txt", &b)] {
+            let tfidf = model.document_tfidf_vector(Path::new(path)).unwrap();
+            for (term, weight) in tfidf {
+                assert_eq!(vector.iter().find(|(dimension, _)| *dimension == space.dimension(&term).unwrap()).unwrap().1, weight);
+            }
+        }
+        assert!(a.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!(space.sparse_vector(Path::new("missing.txt")), None);
+    }
+
+    #[test]
+    fn corrupted_sqlite_indexes_are_reported() {
+        let garbage = TempFile::new("garbage.db");
+        fs::write(&garbage.0, "not an sqlite database at all, just text that is long enough ".repeat(100)).unwrap();
+        assert_eq!(SqliteModel::open(&garbage.0).err(), Some(OpenError::CorruptIndex));
+
+        let truncated = TempFile::new("truncated.db");
+        drop(sqlite_corpus(&truncated));
+        let bytes = fs::read(&truncated.0).unwrap();
+        fs::write(&truncated.0, &bytes[..bytes.len() / 2]).unwrap();
+        assert_eq!(SqliteModel::open(&truncated.0).err(), Some(OpenError::CorruptIndex));
+    }
+
+    #[test]
+    fn corrupted_sqlite_indexes_are_rebuilt() {
+        let file = TempFile::new("rebuilt.db");
+        fs::write(&file.0, "not an sqlite database at all, just text that is long enough ".repeat(100)).unwrap();
+        let stale = ["rebuilt.db-journal", "rebuilt.db-wal", "rebuilt.db-shm"].map(TempFile::new);
+        for file in &stale {
+            fs::write(&file.0, "left over from the corrupted index").unwrap();
+        }
+
+        let mut rebuilt = false;
+        let model = SqliteModel::open_or_rebuild(&file.0, |model| {
+            rebuilt = true;
+            model.add_document(PathBuf::from("a.txt"), &chars("rust search"))?;
+            model.add_document(PathBuf::from("b.txt"), &chars("python"))
+        }).unwrap();
+        assert!(rebuilt);
+        assert!(stale.iter().all(|file| !file.0.exists()));
+        assert_eq!(paths(&model.search_query(&chars("rust")).unwrap()), ["a.txt"]);
+        drop(model);
+
+        // A sound index is opened as is.
+        let model = SqliteModel::open_or_rebuild(&file.0, |_| panic!("rebuilt a sound index")).unwrap();
+        assert_eq!(paths(&model.search_query(&chars("rust")).unwrap()), ["a.txt"]);
+    }
+
+    #[test]
+    fn searches_in_fields_only_score_those_fields() {
+        let mut model = InMemoryModel::default();
+        for (path, title, body, tags) in [
+            ("body.txt", "gardening", "rust on the tools", "outdoors"),
+            ("title.txt", "rust", "ownership and borrowing", "language"),
+            ("tags.txt", "compilers", "parsing", "rust"),
+            ("none.txt", "python", "scripting", "language"),
+        ] {
+            model.add_document_with_fields(PathBuf::from(path), &[("title", &chars(title)), ("body", &chars(body)), ("tags", &chars(tags))]).unwrap();
+        }
+        let found = |fields: &[&str]| {
+            let mut found = model.search_in_fields(&chars("rust"), fields).unwrap().into_iter()
+                .map(|(path, _)| path.to_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            found.sort();
+            found
+        };
+        assert_eq!(found(&["title"]), ["title.txt"]);
+        assert_eq!(found(&["title", "tags"]), ["tags.txt", "title.txt"]);
+        // No fields means all of them.
+        assert_eq!(found(&[]), ["body.txt", "tags.txt", "title.txt"]);
+        assert!(found(&["missing"]).is_empty());
+    }
+
+    #[test]
+    fn acl_searches_fill_k_with_visible_documents() {
+        let mut model = model_of(&[
+            ("forbidden.txt", "rust"),
+            ("other-group.txt", "rust rust notes"),
+            ("own.txt", "rust notes"),
+            ("shared.txt", "rust notes notes"),
+            ("open.txt", "rust notes notes notes"),
+            ("unrelated.txt", "python"),
+        ]);
+        for (path, groups) in [("forbidden.txt", vec![2]), ("other-group.txt", vec![3]), ("own.txt", vec![1]), ("shared.txt", vec![1, 2])] {
+            model.set_document_groups(Path::new(path), groups).unwrap();
+        }
+        let query = chars("rust");
+        // The forbidden documents score higher than any visible one.
+        assert_eq!(paths(&model.search_query(&query).unwrap())[..2], ["forbidden.txt", "other-group.txt"]);
+
+        let search = |groups: &[GroupId], k| model.search_query_acl(&query, &groups.iter().copied().collect(), k).unwrap();
+        assert_eq!(paths(&search(&[1], 2)), ["own.txt", "shared.txt"]);
+        assert_eq!(paths(&search(&[1], 10)), ["own.txt", "shared.txt", "open.txt"]);
+        assert_eq!(paths(&search(&[2], 2)), ["forbidden.txt", "shared.txt"]);
+        assert_eq!(paths(&search(&[], 10)), ["open.txt"]);
+
+        // Scores are those of the unrestricted search.
+        let all = model.search_query(&query).unwrap().into_iter().collect::<HashMap<_, _>>();
+        assert!(search(&[1], 10).iter().all(|(path, score)| all[path] == *score));
+
+        model.set_document_groups(Path::new("own.txt"), []).unwrap();
+        assert_eq!(paths(&model.search_query_acl(&query, &HashSet::new(), 10).unwrap()), ["own.txt", "open.txt"]);
+    }
+
+    #[test]
+    fn older_versions_do_not_overwrite_newer_ones() {
+        let mut model = model_of(&[("other.txt", "unrelated")]);
+        let path = PathBuf::from("doc.txt");
+        assert!(model.update_document_versioned(path.clone(), &chars("first draft"), 1).unwrap());
+        model.set_document_groups(&path, [7]).unwrap();
+        assert!(model.update_document_versioned(path.clone(), &chars("third revision"), 3).unwrap());
+        assert_eq!(model.document_version(&path), Some(3));
+
+        assert!(!model.update_document_versioned(path.clone(), &chars("second edit"), 2).unwrap());
+        assert!(!model.update_document_versioned(path.clone(), &chars("third again"), 3).unwrap());
+        assert_eq!(model.document_version(&path), Some(3));
+        assert_eq!(paths(&model.search_query(&chars("revision")).unwrap()), ["doc.txt"]);
+        assert!(model.search_query(&chars("second")).unwrap().is_empty());
+        assert!(model.search_query(&chars("first")).unwrap().is_empty());
+
+        // The groups survive the updates.
+        assert_eq!(model.doc(&path).unwrap().groups, [7].into_iter().collect());
+        let allowed = HashSet::from([7]);
+        assert_eq!(paths(&model.search_query_acl(&chars("revision"), &allowed, 10).unwrap()), ["doc.txt"]);
+        assert!(model.search_query_acl(&chars("revision"), &HashSet::new(), 10).unwrap().is_empty());
+
+        assert!(model.update_document_versioned(path.clone(), &chars("fourth"), 4).unwrap());
+        assert_eq!(model.doc(&path).unwrap().groups, [7].into_iter().collect());
+    }
+
+    #[test]
+    fn document_dumps_describe_the_indexed_document() {
+        let mut model = InMemoryModel::default().with_max_document_terms(Some(4));
+        model.add_document(PathBuf::from("other.txt"), &chars("unrelated")).unwrap();
+        let path = PathBuf::from("doc.txt");
+        model.update_document_versioned(path.clone(), &chars("rust search rust engine dropped words"), 5).unwrap();
+        model.set_document_groups(&path, [2, 1]).unwrap();
+
+        let dump = serde_json::from_str::<serde_json::Value>(&model.document_debug_json(&path).unwrap()).unwrap();
+        assert_eq!(dump["path"], "doc.txt");
+        assert_eq!(dump["term_count"], 4);
+        assert_eq!(dump["truncated"], true);
+        assert_eq!(dump["version"], 5);
+        assert_eq!(dump["groups"], serde_json::json!([1, 2]));
+        assert_eq!(dump["term_frequencies"], serde_json::json!({"ENGINE": 1, "RUST": 2, "SEARCH": 1}));
+        assert_eq!(dump["positions"]["RUST"], serde_json::json!([0, 2]));
+
+        let dump = serde_json::from_str::<serde_json::Value>(&model.document_debug_json(Path::new("other.txt")).unwrap()).unwrap();
+        assert_eq!(dump["truncated"], false);
+        assert_eq!(dump["version"], serde_json::Value::Null);
+        assert_eq!(dump["groups"], serde_json::json!([]));
+        assert_eq!(model.document_debug_json(Path::new("missing.txt")), None);
+    }
+
+    #[test]
+    fn stale_idfs_are_never_negative() {
+        let fraction = 0.5;
+        let mut model = InMemoryModel::default();
+        for i in 0..10 {
+            let content = if i < 8 { format!("common doc{i}") } else { format!("doc{i}") };
+            model.add_document(PathBuf::from(format!("{i:02}.txt")), &chars(&content)).unwrap();
+        }
+        let mut model = model.with_idf_staleness(fraction);
+        assert_eq!(model.idf_document_count(), 10);
+        // Four more, short of a recomputation, take the term past the stale count.
+        for i in 10..14 {
+            model.add_document(PathBuf::from(format!("{i:02}.txt")), &chars(&format!("common doc{i}"))).unwrap();
+        }
+        assert_eq!(model.idf_document_count(), 10);
+        assert_eq!(model.df["COMMON"], 12);
+
+        let stale = model.explain(&chars("common"), Path::new("00.txt")).unwrap().terms[0].idf;
+        let exact = (14.0f32 / 12.0).log10();
+        assert!(stale >= 0.0, "{stale}");
+        assert!((stale - exact).abs() <= (1.0 + fraction).log10(), "{stale} != {exact}");
+        assert!(model.search_query(&chars("common doc3")).unwrap().iter().all(|(_, score)| *score >= 0.0));
+    }
+
+    #[test]
+    fn repeated_query_terms_can_count_once() {
+        let docs = [("rust.txt", "rust borrowck"), ("python.txt", "python interpreter"), ("c.txt", "pointers")];
+        let query = chars("rust rust python");
+        let weighed = model_of(&docs).search_query(&query).unwrap();
+        let model = model_of(&docs).with_query_term_frequency(false);
+        let collapsed = model.search_query(&query).unwrap();
+        assert_eq!(weighed[0].1, 2.0 * collapsed[0].1);
+        assert_eq!(collapsed, model.search_query(&chars("rust python")).unwrap());
+        // Without the repetition the two documents tie again.
+        assert_eq!(paths(&collapsed), ["python.txt", "rust.txt"]);
+        assert_eq!(collapsed[0].1, collapsed[1].1);
+
+        let explanation = model.explain(&query, Path::new("rust.txt")).unwrap();
+        assert_eq!(explanation.terms[0].weight, 1.0);
+        let tokens = ["RUST", "RUST", "PYTHON"].map(String::from);
+        assert_eq!(model.search_tokens(&tokens).unwrap(), collapsed);
+        // Repetitions do not count against the term cap either.
+        let model = model.with_max_query_terms(2, QueryTermOverflow::Error);
+        assert_eq!(model.search_query(&query).unwrap(), collapsed);
+    }
+}
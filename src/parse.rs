@@ -0,0 +1,116 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+use std::result::Result;
+
+use xml::reader::{XmlEvent, EventReader};
+use xml::common::{Position, TextPosition};
+
+use super::lexer::BOM;
+use super::model::Model;
+
+#[derive(Default, Clone)]
+pub struct IndexOptions {
+    /// Print every file as it gets indexed.
+    pub verbose: bool,
+}
+
+#[derive(Default)]
+pub struct IndexStats {
+    pub indexed: usize,
+    pub skipped: usize,
+}
+
+fn parse_entire_txt_file(file_path: &Path) -> Result<String, ()> {
+    let content = fs::read_to_string(file_path).map_err(|err| {
+        eprintln!("ERROR: coult not open file {file_path}: {err}", file_path = file_path.display());
+    })?;
+    Ok(content.trim_start_matches(BOM).to_string())
+}
+
+pub fn parse_entire_file_by_extension(file_path: &Path) -> Result<String, ()> {
+    let extension = file_path.extension().ok_or_else(|| {
+        eprintln!("ERROR: can't detect file type of {file_path} without extension",
+                  file_path = file_path.display());
+    })?.to_string_lossy();
+    match extension.as_ref() {
+        "xhtml" | "xml" => parse_entire_xml_file(file_path),
+        // TODO: specialized parser for markdown files
+        "txt" | "md" => parse_entire_txt_file(file_path),
+        _ => {
+            eprintln!("ERROR: can't detect file type of {file_path}: unsupported extension {extension}",
+                      file_path = file_path.display(),
+                      extension = extension);
+            Err(())
+        }
+    }
+}
+
+fn parse_entire_xml_file(file_path: &Path) -> Result<String, ()> {
+    let file = File::open(file_path).map_err(|err| {
+        eprintln!("ERROR: could not open file {file_path}: {err}", file_path = file_path.display());
+    })?;
+    let er = EventReader::new(BufReader::new(file));
+    let mut content = String::new();
+    for event in er.into_iter() {
+        let event = event.map_err(|err| {
+            let TextPosition {row, column} = err.position();
+            let msg = err.msg();
+            eprintln!("{file_path}:{row}:{column}: ERROR: {msg}", file_path = file_path.display());
+        })?;
+
+        if let XmlEvent::Characters(text) = event {
+            content.push_str(&text);
+            content.push(' ');
+        }
+    }
+    Ok(content)
+}
+
+/// Indexes every supported file under `dir_path` into `model`, recursively.
+/// Files that cannot be read or parsed are counted as skipped rather than
+/// failing the whole run.
+pub fn add_folder_to_model(dir_path: &Path, model: &mut dyn Model, opts: &IndexOptions, stats: &mut IndexStats) -> Result<(), ()> {
+    let dir = fs::read_dir(dir_path).map_err(|err| {
+        eprintln!("ERROR: could not open directory {dir_path} for indexing: {err}",
+                  dir_path = dir_path.display());
+    })?;
+
+    'next_file: for file in dir {
+        let file = file.map_err(|err| {
+            eprintln!("ERROR: could not read next file in directory {dir_path} during indexing: {err}",
+                      dir_path = dir_path.display());
+        })?;
+
+        let file_path = file.path();
+
+        let file_type = file.file_type().map_err(|err| {
+            eprintln!("ERROR: could not determine type of file {file_path}: {err}",
+                      file_path = file_path.display());
+        })?;
+
+        if file_type.is_dir() {
+            add_folder_to_model(&file_path, model, opts, stats)?;
+            continue 'next_file;
+        }
+
+        // TODO: how does this work with symlinks?
+
+        if opts.verbose {
+            println!("Indexing {:?}...", &file_path);
+        }
+
+        let content = match parse_entire_file_by_extension(&file_path) {
+            Ok(content) => content.chars().collect::<Vec<_>>(),
+            Err(()) => {
+                stats.skipped += 1;
+                continue 'next_file;
+            }
+        };
+
+        model.add_document(file_path, &content)?;
+        stats.indexed += 1;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,315 @@
+use std::result::Result;
+
+/// Boolean query, either parsed from a string with [`Query::parse`] or put
+/// together with the builder methods, which produce identical trees:
+///
+/// `rust AND (search OR index) NOT deprecated` is the same as
+/// `Query::term("rust").and(Query::term("search").or(Query::term("index"))).not(Query::term("deprecated"))`
+///
+/// and `title:rust NOT tags:deprecated` is the same as
+/// `Query::field("title", Query::term("rust")).not(Query::field("tags", Query::term("deprecated")))`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Query {
+    Term(String),
+    Phrase(Vec<String>),
+    /// Matched against the named field of documents indexed with fields only.
+    Field(String, Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+    Not(Box<Query>),
+    Boost(Box<Query>, f32),
+}
+
+impl Query {
+    pub fn term(term: &str) -> Self {
+        Self::Term(term.to_string())
+    }
+
+    pub fn phrase(phrase: &str) -> Self {
+        Self::Phrase(phrase.split_whitespace().map(|word| word.to_string()).collect())
+    }
+
+    pub fn field(field: &str, query: Query) -> Self {
+        Self::Field(field.to_string(), Box::new(query))
+    }
+
+    pub fn and(self, other: Query) -> Self {
+        Self::And(Box::new(self), Box::new(other))
+    }
+
+    pub fn or(self, other: Query) -> Self {
+        Self::Or(Box::new(self), Box::new(other))
+    }
+
+    /// Documents matching `self` but not `other`.
+    pub fn not(self, other: Query) -> Self {
+        self.and(Self::Not(Box::new(other)))
+    }
+
+    pub fn boost(self, boost: f32) -> Self {
+        Self::Boost(Box::new(self), boost)
+    }
+
+    /// Parses `AND`, `OR` and `NOT` (binding tighter in reverse order),
+    /// parentheses, `"quoted phrases"`, `term^2.5` boosts and `field:` prefixes
+    /// scoping a term, phrase or parenthesized query to a field. Adjacent terms
+    /// without an operator are joined with `AND`.
+    pub fn parse(source: &str) -> Result<Self, ()> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens: &tokens, source };
+        let query = parser.parse_or()?;
+        if let Some(token) = parser.tokens.first() {
+            eprintln!("ERROR: unexpected {token:?} in query {source:?}");
+            return Err(())
+        }
+        Ok(query)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Phrase(Vec<String>),
+    Field(String),
+    Boost(f32),
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, ()> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&x) = chars.peek() {
+        match x {
+            _ if x.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let phrase = chars.by_ref().take_while(|x| *x != '"').collect::<String>();
+                tokens.push(Token::Phrase(phrase.split_whitespace().map(|word| word.to_string()).collect()));
+            }
+            '^' => {
+                chars.next();
+                let mut boost = String::new();
+                while let Some(x) = chars.next_if(|x| x.is_ascii_digit() || *x == '.') {
+                    boost.push(x);
+                }
+                let boost = boost.parse().map_err(|err| {
+                    eprintln!("ERROR: invalid boost ^{boost} in query {source:?}: {err}");
+                })?;
+                tokens.push(Token::Boost(boost));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(x) = chars.next_if(|x| !x.is_whitespace() && !"()\"^:".contains(*x)) {
+                    word.push(x);
+                }
+                // A colon right after a word and before something to search
+                // makes the word a field name, anywhere else it is part of it.
+                if chars.peek() == Some(&':') {
+                    chars.next();
+                    if !word.is_empty() && chars.peek().is_some_and(|x| !x.is_whitespace()) {
+                        tokens.push(Token::Field(word));
+                        continue
+                    }
+                    word.push(':');
+                    while let Some(x) = chars.next_if(|x| !x.is_whitespace() && !"()\"^".contains(*x)) {
+                        word.push(x);
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    source: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.tokens.first(), Some(Token::Word(word)) if word == keyword)
+    }
+
+    fn advance(&mut self) -> Option<&'a Token> {
+        let (first, rest) = self.tokens.split_first()?;
+        self.tokens = rest;
+        Some(first)
+    }
+
+    fn parse_or(&mut self) -> Result<Query, ()> {
+        let mut query = self.parse_and()?;
+        while self.peek_keyword("OR") {
+            self.advance();
+            query = query.or(self.parse_and()?);
+        }
+        Ok(query)
+    }
+
+    fn parse_and(&mut self) -> Result<Query, ()> {
+        let mut query = self.parse_unary()?;
+        loop {
+            if self.peek_keyword("AND") {
+                self.advance();
+                query = query.and(self.parse_unary()?);
+            } else if self.peek_keyword("NOT") {
+                self.advance();
+                query = query.not(self.parse_unary()?);
+            } else if self.peek_keyword("OR") || matches!(self.tokens.first(), None | Some(Token::Close)) {
+                return Ok(query)
+            } else {
+                query = query.and(self.parse_unary()?);
+            }
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<Query, ()> {
+        if self.peek_keyword("NOT") {
+            self.advance();
+            return Ok(Query::Not(Box::new(self.parse_unary()?)))
+        }
+        let mut query = self.parse_primary()?;
+        while let Some(Token::Boost(boost)) = self.tokens.first() {
+            self.advance();
+            query = query.boost(*boost);
+        }
+        Ok(query)
+    }
+
+    fn parse_primary(&mut self) -> Result<Query, ()> {
+        match self.advance() {
+            Some(Token::Word(word)) => Ok(Query::Term(word.clone())),
+            Some(Token::Phrase(words)) => Ok(Query::Phrase(words.clone())),
+            Some(Token::Field(field)) => {
+                if matches!(self.tokens.first(), Some(Token::Field(_))) {
+                    eprintln!("ERROR: nested field {field}: in query {source:?}", source = self.source);
+                    return Err(())
+                }
+                Ok(Query::field(field, self.parse_primary()?))
+            }
+            Some(Token::Open) => {
+                let query = self.parse_or()?;
+                if self.advance() != Some(&Token::Close) {
+                    eprintln!("ERROR: unbalanced parentheses in query {source:?}", source = self.source);
+                    return Err(())
+                }
+                Ok(query)
+            }
+            Some(token) => {
+                eprintln!("ERROR: unexpected {token:?} in query {source:?}", source = self.source);
+                Err(())
+            }
+            None => {
+                eprintln!("ERROR: unexpected end of query {source:?}", source = self.source);
+                Err(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    use crate::model::{InMemoryModel, Model};
+
+    #[test]
+    fn parsed_queries_equal_built_ones() {
+        let cases = [
+            ("rust", Query::term("rust")),
+            ("rust search", Query::term("rust").and(Query::term("search"))),
+            ("rust AND (search OR index) NOT deprecated",
+             Query::term("rust").and(Query::term("search").or(Query::term("index"))).not(Query::term("deprecated"))),
+            ("a OR b AND c", Query::term("a").or(Query::term("b").and(Query::term("c")))),
+            ("\"search engine\" OR rust^2.5", Query::phrase("search engine").or(Query::term("rust").boost(2.5))),
+            ("(a OR b)^2 c", Query::term("a").or(Query::term("b")).boost(2.0).and(Query::term("c"))),
+            ("NOT a", Query::Not(Box::new(Query::term("a")))),
+            // A colon before whitespace does not make a field.
+            ("note: rust", Query::term("note:").and(Query::term("rust"))),
+        ];
+        for (source, built) in cases {
+            assert_eq!(Query::parse(source).unwrap(), built, "{source}");
+        }
+    }
+
+    #[test]
+    fn parsed_and_built_queries_search_the_same() {
+        let mut model = InMemoryModel::default();
+        for (path, content) in [("a.txt", "rust search engine"), ("b.txt", "rust index deprecated"), ("c.txt", "rust index"), ("d.txt", "python search")] {
+            model.add_document(PathBuf::from(path), &content.chars().collect::<Vec<_>>()).unwrap();
+        }
+        let parsed = Query::parse("rust AND (search OR index) NOT deprecated").unwrap();
+        let built = Query::term("rust").and(Query::term("search").or(Query::term("index"))).not(Query::term("deprecated"));
+        let ranking = model.search_boolean(&parsed).unwrap();
+        assert_eq!(ranking, model.search_boolean(&built).unwrap());
+        assert_eq!(ranking.iter().map(|(path, _)| path.to_str().unwrap()).collect::<Vec<_>>(), ["c.txt", "a.txt"]);
+    }
+
+    #[test]
+    fn malformed_queries_are_errors() {
+        for source in ["(rust", "rust)", "((a OR b)", "rust^", "rust^x", "rust^1.2.3", "a AND", "a OR", "()"] {
+            assert!(Query::parse(source).is_err(), "{source}");
+        }
+    }
+
+    #[test]
+    fn field_scopes_nest_in_boolean_queries() {
+        let title = |query| Query::field("title", query);
+        let body = |query| Query::field("body", query);
+        let cases = [
+            ("title:rust AND (body:async OR body:concurrency) NOT tags:deprecated",
+             title(Query::term("rust")).and(body(Query::term("async")).or(body(Query::term("concurrency"))))
+                 .not(Query::field("tags", Query::term("deprecated")))),
+            // The scope covers a single term, so AND and OR keep their precedence around it.
+            ("title:a OR b AND body:c", title(Query::term("a")).or(Query::term("b").and(body(Query::term("c"))))),
+            ("title:(a OR b) c", title(Query::term("a").or(Query::term("b"))).and(Query::term("c"))),
+            ("body:\"search engine\"^2", body(Query::phrase("search engine")).boost(2.0)),
+            ("NOT tags:x", Query::Not(Box::new(Query::field("tags", Query::term("x"))))),
+            ("rust NOT tags:x", Query::term("rust").not(Query::field("tags", Query::term("x")))),
+        ];
+        for (source, built) in cases {
+            assert_eq!(Query::parse(source).unwrap(), built, "{source}");
+        }
+        assert!(Query::parse("title:body:rust").is_err());
+    }
+
+    #[test]
+    fn field_constraints_filter_boolean_searches() {
+        let mut model = InMemoryModel::default();
+        for (path, title, body, tags) in [
+            ("a.txt", "rust", "async runtimes", "current"),
+            ("b.txt", "rust", "concurrency primitives", "deprecated"),
+            ("c.txt", "python", "async rust", "current"),
+            ("d.txt", "rust", "borrow checker", "current"),
+        ] {
+            let fields = [("title", title), ("body", body), ("tags", tags)].map(|(name, content)| (name, content.chars().collect::<Vec<_>>()));
+            let fields = fields.iter().map(|(name, content)| (*name, content.as_slice())).collect::<Vec<_>>();
+            model.add_document_with_fields(PathBuf::from(path), &fields).unwrap();
+        }
+        let search = |source| {
+            let ranking = model.search_boolean(&Query::parse(source).unwrap()).unwrap();
+            let mut paths = ranking.iter().map(|(path, _)| path.to_str().unwrap().to_string()).collect::<Vec<_>>();
+            paths.sort();
+            paths
+        };
+        // c.txt has rust and async, but not rust in its title.
+        assert_eq!(search("title:rust AND body:async"), ["a.txt"]);
+        assert_eq!(search("rust AND async"), ["a.txt", "c.txt"]);
+        assert_eq!(search("title:rust AND (body:async OR body:concurrency)"), ["a.txt", "b.txt"]);
+        assert_eq!(search("title:rust AND (body:async OR body:concurrency) NOT tags:deprecated"), ["a.txt"]);
+        assert_eq!(search("title:rust NOT tags:deprecated"), ["a.txt", "d.txt"]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::path::{Path, PathBuf};
+use std::result::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use super::model::{Model, Ranking};
+
+/// A query that went through a [`RecordingModel`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueryRecord {
+    pub query: String,
+    /// Number of results, `None` if the search failed.
+    pub results: Option<usize>,
+    pub latency: Duration,
+}
+
+type Sink = Box<dyn FnMut(&QueryRecord) + Send>;
+
+/// Wraps any [`Model`] and passes every query searched through it to a sink,
+/// e.g. to replay the workload later or to collect query suggestions. The sink
+/// is a closure so it can push to a `Vec`, append to a file or anything else.
+/// Recording can be turned off and on while running; when off, searches cost
+/// one atomic load more than on the inner model.
+pub struct RecordingModel<M: Model> {
+    inner: M,
+    sink: Mutex<Sink>,
+    enabled: AtomicBool,
+}
+
+impl<M: Model> RecordingModel<M> {
+    pub fn new<S>(inner: M, sink: S) -> Self where S: FnMut(&QueryRecord) + Send + 'static {
+        Self { inner, sink: Mutex::new(Box::new(sink)), enabled: AtomicBool::new(true) }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> M {
+        self.inner
+    }
+}
+
+impl<M: Model> Model for RecordingModel<M> {
+    fn search_query(&self, query: &[char]) -> Result<Ranking, ()> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return self.inner.search_query(query)
+        }
+        let start = Instant::now();
+        let result = self.inner.search_query(query);
+        let record = QueryRecord {
+            query: query.iter().collect(),
+            results: result.as_ref().ok().map(|ranking| ranking.len()),
+            latency: start.elapsed(),
+        };
+        // A sink that panicked once is still called for the records after.
+        (self.sink.lock().unwrap_or_else(PoisonError::into_inner))(&record);
+        result
+    }
+
+    fn add_document(&mut self, file_path: PathBuf, content: &[char]) -> Result<(), ()> {
+        self.inner.add_document(file_path, content)
+    }
+
+    fn estimate_cost(&self, query: &[char]) -> Result<usize, ()> {
+        self.inner.estimate_cost(query)
+    }
+
+    fn remove_documents(&mut self, paths: &[PathBuf]) -> Result<usize, ()> {
+        self.inner.remove_documents(paths)
+    }
+
+    fn document_count(&self) -> Result<usize, ()> {
+        self.inner.document_count()
+    }
+
+    fn most_frequent_terms(&self, n: usize) -> Result<Vec<(String, usize)>, ()> {
+        self.inner.most_frequent_terms(n)
+    }
+
+    fn remove_document(&mut self, path: &Path) -> Result<bool, ()> {
+        self.inner.remove_document(path)
+    }
+
+    fn compact_paths(&mut self) -> Result<(), ()> {
+        self.inner.compact_paths()
+    }
+
+    fn prewarm_from_queries(&self, queries: &[Vec<char>]) -> Result<(), ()> {
+        self.inner.prewarm_from_queries(queries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::model::InMemoryModel;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    #[test]
+    fn queries_are_recorded_in_order() {
+        let mut inner = InMemoryModel::default();
+        for (path, content) in [("a.txt", "rust search"), ("b.txt", "rust index"), ("c.txt", "python")] {
+            inner.add_document(PathBuf::from(path), &chars(content)).unwrap();
+        }
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let sink = records.clone();
+        let model = RecordingModel::new(inner, move |record: &QueryRecord| sink.lock().unwrap().push(record.clone()));
+
+        let queries = ["rust", "python index", "missing"];
+        for query in queries {
+            assert_eq!(model.search_query(&chars(query)).unwrap(), model.inner().search_query(&chars(query)).unwrap());
+        }
+        model.set_enabled(false);
+        model.search_query(&chars("not recorded")).unwrap();
+        model.set_enabled(true);
+        model.search_query(&chars("search")).unwrap();
+
+        let records = records.lock().unwrap();
+        let recorded = records.iter().map(|record| (record.query.as_str(), record.results)).collect::<Vec<_>>();
+        assert_eq!(recorded, [("rust", Some(2)), ("python index", Some(2)), ("missing", Some(0)), ("search", Some(1))]);
+    }
+}
@@ -99,7 +99,7 @@ fn serve_request(request: Request, model: &impl Model) -> io::Result<()> {
 
 
 pub fn start(address: &str, model: &impl Model) -> Result<(), ()> {
-    let server = Server::http(&address).map_err(|err| {
+    let server = Server::http(address).map_err(|err| {
         eprintln!("ERROR: could not start HTTP server at {address}: {err}");
     })?;
 